@@ -22,6 +22,10 @@
 # (this can be overwritten with the `UME_UPLOADER_KEY` environment variable
 # or use `-e UME_UPLOADER_KEY=<some key here>` when running with Docker and
 # this won't be used)
+#
+# to keep the key itself out of this file, run `ume server hash-key` and
+# use the `$argon2id$...` hash it prints instead. only Argon2 hashes are
+# supported, bcrypt hashes are rejected.
 uploader_key = "a uploader key that is here because no one has bothered to update this, please update this or you will be laughed at"
 
 storage "filesystem" {
@@ -173,20 +173,13 @@ pub async fn execute(mut cmd: Cmd) -> eyre::Result<()> {
 
     let contents = fs::read(&file).map(Bytes::from)?;
 
-    let ct = azalia::remi::fs::default_resolver(&contents).parse::<mime::Mime>()?;
-    assert!(ct.type_() == mime::IMAGE);
-
-    let ext = match ct.subtype() {
-        mime::PNG => "png",
-        mime::JPEG => "jpg",
-        mime::GIF => "gif",
-        mime::SVG => "svg",
-        _ => unreachable!(),
-    };
+    let ext = crate::format::ImageFormat::sniff(&contents)
+        .ok_or_else(|| eyre!("file {} is not an image that ume supports", file.display()))?
+        .extension();
 
     let res = client
         .post(server.join("images/upload")?)
-        .header("Authorization", &uploader_key)
+        .header("Authorization", format!("Uploader {uploader_key}"))
         .multipart(Form::new().part(
             "fdata",
             Part::stream(contents.clone()).file_name(format!("unknown.{ext}")),
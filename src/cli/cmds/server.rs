@@ -13,6 +13,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod hash_key;
+
 use crate::config::{self, Config};
 use azalia::log::{WriteLayer, writers};
 use opentelemetry::{InstrumentationScope, KeyValue, trace::TracerProvider};
@@ -37,9 +39,21 @@ pub struct Cmd {
     /// list of Tokio workers to use, this will be limited to your CPU cores.
     #[arg(long, short = 'w', env = "UME_SERVER_WORKERS")]
     pub workers: Option<usize>,
+
+    #[command(subcommand)]
+    command: Option<Subcmd>,
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+enum Subcmd {
+    HashKey(hash_key::Cmd),
 }
 
 pub async fn execute(cmd: Cmd) -> eyre::Result<()> {
+    if let Some(Subcmd::HashKey(cmd)) = cmd.command {
+        return hash_key::execute(cmd);
+    }
+
     let loc = match cmd.config {
         Some(ref path) => format!("in path [{}]", path.display()),
         None => match Config::find_default_location() {
@@ -123,27 +137,7 @@ pub async fn execute(cmd: Cmd) -> eyre::Result<()> {
         .init();
 
     info!("loaded configuration from {loc}, starting Ume server...");
-    let storage = match config.storage.clone() {
-        crate::config::storage::Config::Filesystem(fs) => {
-            azalia::remi::StorageService::Filesystem(azalia::remi::fs::StorageService::with_config(fs))
-        }
-
-        crate::config::storage::Config::Azure(azure) => {
-            azalia::remi::StorageService::Azure(azalia::remi::azure::StorageService::new(azure)?)
-        }
-
-        crate::config::storage::Config::Gridfs(gridfs) => {
-            let client = azalia::remi::gridfs::mongodb::Client::with_options(gridfs.client_options.clone())?;
-            azalia::remi::StorageService::Gridfs(azalia::remi::gridfs::StorageService::from_client(&client, gridfs))
-        }
-
-        crate::config::storage::Config::S3(s3) => {
-            azalia::remi::StorageService::S3(azalia::remi::s3::StorageService::new(s3))
-        }
-    };
-
-    <azalia::remi::StorageService as azalia::remi::core::StorageService>::init(&storage).await?;
-    crate::server::start_server(storage, config).await
+    crate::server::start_server(config).await
 }
 
 fn print_banner() {
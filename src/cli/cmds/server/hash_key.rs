@@ -0,0 +1,59 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::{self, BufRead, Write as _};
+
+/// Hashes an uploader key with Argon2 so it can be used as `uploader_key` or in an
+/// uploader's `keys` without the key itself being written to disk.
+///
+/// The hash is written to stdout. If `--generate` is passed, the generated key is
+/// written to stderr so it can be handed out to whoever needs it.
+#[derive(Debug, Clone, clap::Parser)]
+pub struct Cmd {
+    /// the key to hash. If this isn't given, then it'll be read from stdin.
+    key: Option<String>,
+
+    /// generates a new key and hashes it instead.
+    #[arg(long, short = 'g', conflicts_with = "key")]
+    generate: bool,
+}
+
+pub fn execute(cmd: Cmd) -> eyre::Result<()> {
+    let key = match (cmd.key, cmd.generate) {
+        (Some(key), _) => key,
+        (None, true) => {
+            let key = crate::config::__generated_uploader_key();
+            eprintln!("{key}");
+
+            key
+        }
+
+        (None, false) => {
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+
+            line.trim_end_matches(['\r', '\n']).to_owned()
+        }
+    };
+
+    if key.is_empty() {
+        bail!("refusing to hash an empty uploader key");
+    }
+
+    let hash = crate::config::hash_key(&key)?;
+    writeln!(io::stdout().lock(), "{hash}")?;
+
+    Ok(())
+}
@@ -56,8 +56,8 @@ pub fn execute(cmd: Cmd) -> eyre::Result<()> {
         "Body": "MultipartFormData",
         "FileFormName": "fdata",
         "URL": format!("{}/images/{{json:filename}}", cmd.server),
-        "ThumbnailURL": format!("{}/images/{{json:filename}}", cmd.server),
-        "DeletionURL": null,
+        "ThumbnailURL": "{json:thumbnail_url}",
+        "DeletionURL": "{json:deletion_url}",
         "ErrorMessage": cmd.error_message.unwrap_or(format!("failed to upload to {}: {{json:message}}", cmd.server)),
         "Headers": json!({
             "Authorization": format!("Uploader {}", cmd.uploader_key)
@@ -0,0 +1,137 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config::util;
+use azalia::config::{
+    env::{self, TryFromEnv},
+    merge::Merge,
+};
+use charted_core::serde::Duration;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+pub const MAX_SIZE: &str = "UME_FETCH_MAX_SIZE";
+pub const TIMEOUT: &str = "UME_FETCH_TIMEOUT";
+pub const MAX_REDIRECTS: &str = "UME_FETCH_MAX_REDIRECTS";
+pub const MAX_CONCURRENT: &str = "UME_FETCH_MAX_CONCURRENT";
+pub const ALLOW_PRIVATE_ADDRESSES: &str = "UME_FETCH_ALLOW_PRIVATE_ADDRESSES";
+
+/// ## `[fetch]` table
+/// Configures how images are downloaded by the `POST /images/fetch` endpoint.
+///
+/// ## Example
+/// ```toml
+/// [fetch]
+/// max_size = 5242880
+/// timeout = "5s"
+/// ```
+#[derive(Debug, Clone, Merge, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Largest image (in bytes) that can be downloaded.
+    #[serde(default = "__default_max_size")]
+    pub max_size: usize,
+
+    /// How long downloading an image can take, from connecting to receiving the last byte.
+    #[serde(default = "__default_timeout")]
+    #[merge(strategy = __merge_timeout)]
+    pub timeout: Duration,
+
+    /// How many redirects are followed before giving up.
+    #[serde(default = "__default_max_redirects")]
+    pub max_redirects: usize,
+
+    /// How many images each uploader can be downloading at the same time.
+    #[serde(default = "__default_max_concurrent")]
+    pub max_concurrent: usize,
+
+    /// Whether images can be downloaded from loopback, private, link-local and other
+    /// addresses that aren't reachable from the internet. These are refused by default,
+    /// since they would let uploaders reach services that are only meant to be reached
+    /// by the server, like cloud metadata endpoints.
+    #[serde(default)]
+    #[merge(strategy = azalia::config::merge::strategy::bool::only_if_falsy)]
+    pub allow_private_addresses: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_size: __default_max_size(),
+            timeout: __default_timeout(),
+            max_redirects: __default_max_redirects(),
+            max_concurrent: __default_max_concurrent(),
+            allow_private_addresses: false,
+        }
+    }
+}
+
+impl TryFromEnv for Config {
+    type Error = eyre::Report;
+
+    fn try_from_env() -> Result<Self, Self::Error> {
+        Ok(Config {
+            max_size: env::try_parse_or_else(MAX_SIZE, __default_max_size())?,
+            timeout: match env::try_parse_optional::<_, String>(TIMEOUT)? {
+                Some(value) => Duration::from_str(&value)?,
+                None => __default_timeout(),
+            },
+
+            max_redirects: env::try_parse_or_else(MAX_REDIRECTS, __default_max_redirects())?,
+            max_concurrent: env::try_parse_or_else(MAX_CONCURRENT, __default_max_concurrent())?,
+            allow_private_addresses: util::bool_env(ALLOW_PRIVATE_ADDRESSES)?,
+        })
+    }
+}
+
+impl Config {
+    pub(crate) fn validate(&self) -> eyre::Result<()> {
+        if self.max_size == 0 {
+            bail!("`fetch.max_size` must be greater than zero");
+        }
+
+        if std::time::Duration::from(self.timeout).is_zero() {
+            bail!("`fetch.timeout` must be longer than zero");
+        }
+
+        if self.max_concurrent == 0 {
+            bail!("`fetch.max_concurrent` must be greater than zero");
+        }
+
+        Ok(())
+    }
+}
+
+const fn __default_max_size() -> usize {
+    15 * 1024 * 1024
+}
+
+fn __default_timeout() -> Duration {
+    Duration::from(std::time::Duration::from_secs(10))
+}
+
+const fn __default_max_redirects() -> usize {
+    5
+}
+
+const fn __default_max_concurrent() -> usize {
+    2
+}
+
+fn __merge_timeout(me: &mut Duration, other: Duration) {
+    if std::time::Duration::from(*me) != std::time::Duration::from(other) {
+        *me = other;
+    }
+}
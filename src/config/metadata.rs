@@ -0,0 +1,96 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use azalia::config::{
+    env::{self, TryFromEnv},
+    merge::Merge,
+};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+pub const STORE: &str = "UME_METADATA_STORE";
+pub const SQLITE_PATH: &str = "UME_METADATA_SQLITE_PATH";
+
+/// ## `[metadata]` table
+/// Configures where **ume** keeps the metadata of every upload (who uploaded it, when, its
+/// size, dimensions and hash).
+///
+/// ## Examples
+/// ### SQLite
+/// ```toml
+/// [metadata.sqlite]
+/// path = "/var/lib/noel/ume/metadata.db"
+/// ```
+///
+/// ### Sidecar
+/// ```toml
+/// metadata = "sidecar"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Config {
+    /// Keeps metadata in an embedded SQLite database.
+    Sqlite(Sqlite),
+
+    /// Keeps metadata as JSON objects in the storage service, next to the images
+    /// themselves. This is useful when the server has no persistent disk to keep
+    /// a database on.
+    Sidecar,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct Sqlite {
+    /// Path to the database file. By default, this is `metadata.db` in the same
+    /// directory that the generated uploader key is kept in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config::Sqlite(Sqlite::default())
+    }
+}
+
+impl TryFromEnv for Config {
+    type Error = eyre::Report;
+
+    fn try_from_env() -> Result<Self, Self::Error> {
+        crate::config::impl_enum_based_env_value!(STORE, {
+            on match fail: |input| "environment variable `${}` is invalid: expected `sqlite` or `sidecar`: received '{}' instead!" [STORE, input];
+
+            "sqlite" | "" => Ok(Config::Sqlite(Sqlite {
+                path: env::try_parse_optional(SQLITE_PATH)?
+            }));
+
+            "sidecar" | "json" => Ok(Config::Sidecar);
+        })
+    }
+}
+
+impl Merge for Config {
+    fn merge(&mut self, other: Self) {
+        match (self, other) {
+            (Self::Sqlite(me), Self::Sqlite(other)) => {
+                me.merge(other);
+            }
+
+            (me, other) => {
+                *me = other;
+            }
+        }
+    }
+}
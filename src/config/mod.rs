@@ -13,37 +13,88 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod fetch;
 pub mod logging;
+pub mod metadata;
+pub mod naming;
+pub mod state;
 pub mod storage;
 pub mod tracing;
+pub mod transforms;
+pub mod uploader;
+pub mod uploads;
 pub mod util;
 
+use argon2::{
+    Argon2, PasswordHash, PasswordHasher,
+    password_hash::SaltString,
+};
 use azalia::config::{
     env::{self, TryFromEnv},
     merge::Merge,
 };
+use charted_core::serde::Duration;
 use rand::distr::{Alphanumeric, SampleString};
 use sentry::types::Dsn;
 use serde::{Deserialize, Serialize};
 use std::{
     fs,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 use url::Url;
 
 const UPLOADER_KEY: &str = "UME_UPLOADER_KEY";
+const SECRET_KEY: &str = "UME_SECRET_KEY";
+const MAX_SIGNED_TTL: &str = "UME_MAX_SIGNED_TTL";
 const SENTRY_DSN: &str = "UME_SENTRY_DSN";
 const BASE_URL: &str = "UME_BASE_URL";
+const STATE_DIR: &str = "UME_STATE_DIR";
 
 #[derive(Debug, Clone, Serialize, Deserialize, Merge)]
 pub struct Config {
+    /// Key that uploaders use to authenticate with. This can either be the key itself or,
+    /// to keep it out of the configuration file, an Argon2 hash of it in the PHC string
+    /// format that `ume server hash-key` generates. Other hashes (like bcrypt) aren't
+    /// supported, so every hashed key costs the same to verify.
     #[merge(strategy = azalia::config::merge::strategy::strings::overwrite_empty)]
     #[serde(default)]
     pub uploader_key: String,
 
+    /// List of named uploader accounts. The `uploader_key` is still accepted and acts
+    /// as an account called `default` that has every scope.
+    #[serde(
+        default,
+        alias = "uploader",
+        deserialize_with = "uploader::deserialize",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub uploaders: Vec<uploader::Config>,
+
+    /// Key that signed URLs are signed with. If this isn't set, then one is generated and
+    /// kept next to the generated uploader key.
+    #[merge(strategy = azalia::config::merge::strategy::strings::overwrite_empty)]
+    #[serde(default, skip_serializing)]
+    pub secret_key: String,
+
+    /// Longest that a signed URL can be valid for, so that one that leaked can't be used
+    /// to get an image forever. By default, this is 7 days.
+    #[serde(default = "__default_max_signed_ttl")]
+    #[merge(strategy = __merge_max_signed_ttl)]
+    pub max_signed_ttl: Duration,
+
     #[serde(default = "__default_base_url")]
     pub base_url: Url,
 
+    /// Directory where the server keeps state that it generated itself, like the uploader
+    /// key when none was configured. By default, this is the `.ume` directory in the data
+    /// directory with the filesystem storage service, or the directory of the configuration
+    /// file otherwise, falling back to the platform's data directory if that can't be
+    /// written to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[merge(strategy = __merge_state_dir)]
+    pub state_dir: Option<PathBuf>,
+
     #[serde(default, skip_serializing_if = "Option::is_some")]
     pub sentry_dsn: Option<Dsn>,
 
@@ -53,6 +104,21 @@ pub struct Config {
     #[serde(default)]
     pub storage: storage::Config,
 
+    #[serde(default)]
+    pub metadata: metadata::Config,
+
+    #[serde(default)]
+    pub uploads: uploads::Config,
+
+    #[serde(default)]
+    pub naming: naming::Config,
+
+    #[serde(default)]
+    pub fetch: fetch::Config,
+
+    #[serde(default)]
+    pub transforms: transforms::Config,
+
     #[serde(default)]
     pub tracing: tracing::Config,
 
@@ -64,17 +130,45 @@ fn __default_base_url() -> Url {
     Url::parse("http://localhost:3621").expect("failed to parse as url")
 }
 
+fn __default_max_signed_ttl() -> Duration {
+    Duration::from(std::time::Duration::from_secs(7 * 24 * 60 * 60))
+}
+
+fn __merge_max_signed_ttl(me: &mut Duration, other: Duration) {
+    if std::time::Duration::from(other) != std::time::Duration::from(__default_max_signed_ttl()) {
+        *me = other;
+    }
+}
+
+fn __merge_state_dir(me: &mut Option<PathBuf>, other: Option<PathBuf>) {
+    if other.is_some() {
+        *me = other;
+    }
+}
+
 impl TryFromEnv for Config {
     type Error = eyre::Report;
 
     fn try_from_env() -> Result<Self, Self::Error> {
         Ok(Config {
             uploader_key: env::try_parse(UPLOADER_KEY).unwrap_or_default(),
+            uploaders: Vec::new(),
+            secret_key: env::try_parse(SECRET_KEY).unwrap_or_default(),
+            max_signed_ttl: match env::try_parse_optional::<_, String>(MAX_SIGNED_TTL)? {
+                Some(value) => Duration::from_str(&value)?,
+                None => __default_max_signed_ttl(),
+            },
             sentry_dsn: env::try_parse_optional(SENTRY_DSN)?,
             base_url: env::try_parse_or(BASE_URL, __default_base_url)?,
+            state_dir: env::try_parse_optional(STATE_DIR)?,
 
             logging: logging::Config::try_from_env()?,
             storage: storage::Config::try_from_env()?,
+            metadata: metadata::Config::try_from_env()?,
+            uploads: uploads::Config::try_from_env()?,
+            naming: naming::Config::try_from_env()?,
+            fetch: fetch::Config::try_from_env()?,
+            transforms: transforms::Config::try_from_env()?,
             tracing: tracing::Config::try_from_env()?,
             server: crate::server::Config::try_from_env()?,
         })
@@ -122,43 +216,131 @@ impl Config {
     /// Creates a new [`Config`] instance from a given path.
     pub fn new<P: AsRef<Path>>(path: Option<P>) -> eyre::Result<Config> {
         // priority: config file > env variables
-        let Some(path) = path.as_ref() else {
-            return Config::try_from_env();
-        };
-
-        let path = path.as_ref();
-        if !path.try_exists()? {
-            eprintln!(
-                "[ume WARN] file {} doesn't exist, using system env variables",
-                path.display()
-            );
+        let path = path.as_ref().map(AsRef::as_ref);
+        let mut cfg = Config::try_from_env()?;
+
+        match path {
+            Some(path) if path.try_exists()? => {
+                let file = toml::from_str(&fs::read_to_string(path)?)?;
+                cfg.merge(file);
+            }
+
+            Some(path) => {
+                eprintln!(
+                    "[ume WARN] file {} doesn't exist, using system env variables",
+                    path.display()
+                );
+            }
 
-            return Config::try_from_env();
+            None => {}
         }
 
-        let mut cfg = Config::try_from_env()?;
-        let file = toml::from_str(&fs::read_to_string(path)?)?;
+        uploader::validate(&cfg.uploaders)?;
+        cfg.uploads.validate()?;
+        cfg.naming.validate()?;
+        cfg.fetch.validate()?;
+        cfg.server.validate()?;
+        validate_hashed_keys(&cfg)?;
 
-        cfg.merge(file);
+        if std::time::Duration::from(cfg.max_signed_ttl).is_zero() {
+            bail!("`max_signed_ttl` must be longer than zero");
+        }
 
-        if cfg.uploader_key.is_empty() {
-            let key = __generated_uploader_key();
-            eprintln!("[ume WARN] Missing a uploader key for authentication! I have generated one for you:\n
-\t\t{key}\n
+        let directories = state::directories(&cfg, path);
+        if cfg.uploader_key.is_empty() && cfg.uploaders.is_empty() {
+            let state = state::load_or_generate(&directories, "uploader-key", __generated_uploader_key)?;
+            if state.generated {
+                let saved = match state.path {
+                    Some(ref path) => format!("It was saved in {} and will be reused on every start.", path.display()),
+                    None => String::from("It couldn't be saved, so a new one will be generated on the next start."),
+                };
+
+                eprintln!("[ume WARN] Missing a uploader key for authentication! I have generated one for you:\n
+\t\t{}\n
+{saved}
 Set this in the `UME_UPLOADER_KEY` environment variable when loading the server or in the `uploader_key` in your `config.hcl` file.
-If any other key replaces this, then it'll no longer be verified. It is recommended to keep this safe somewhere");
+If any other key replaces this, then it'll no longer be verified. It is recommended to keep this safe somewhere", state.value);
+            }
+
+            cfg.uploader_key = state.value;
+        }
+
+        if cfg.secret_key.is_empty() {
+            let state = state::load_or_generate(&directories, "secret-key", || {
+                Alphanumeric.sample_string(&mut rand::rng(), 64)
+            })?;
+
+            if state.path.is_none() {
+                eprintln!(
+                    "[ume WARN] signed URLs will stop working when the server stops, set `secret_key` to keep them working"
+                );
+            }
+
+            cfg.secret_key = state.value;
+        }
 
-            cfg.uploader_key = key;
+        if let metadata::Config::Sqlite(metadata::Sqlite { path: None }) = cfg.metadata {
+            cfg.metadata = metadata::Config::Sqlite(metadata::Sqlite {
+                path: Some(state::writable(&directories).join("metadata.db")),
+            });
         }
 
         Ok(cfg)
     }
 }
 
-fn __generated_uploader_key() -> String {
+pub(crate) fn __generated_uploader_key() -> String {
     Alphanumeric.sample_string(&mut rand::rng(), 32)
 }
 
+/// Returns `true` if `key` looks like a password hash in the [PHC string format] rather
+/// than a plaintext key.
+///
+/// [PHC string format]: https://github.com/P-H-C/phc-string-format/blob/master/phc-sf-spec.md
+pub fn is_hashed_key(key: &str) -> bool {
+    key.starts_with('$')
+}
+
+/// Hashes `key` with Argon2 into a PHC string that can be used in place of a plaintext key.
+/// The passwords of password-protected images are hashed with this as well.
+///
+/// Argon2 is intentionally slow, so this should be called from a blocking context.
+pub fn hash_key(key: &str) -> eyre::Result<String> {
+    let mut salt = [0u8; 16];
+    rand::Rng::fill(&mut rand::rng(), &mut salt);
+
+    let salt = SaltString::encode_b64(&salt).map_err(|e| eyre!("failed to encode salt: {e}"))?;
+    Argon2::default()
+        .hash_password(key.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| eyre!("failed to hash key: {e}"))
+}
+
+/// Checks that every hashed key in the configuration is a valid Argon2 PHC string. The
+/// keys themselves are left out of the errors, since they end up in logs.
+fn validate_hashed_keys(config: &Config) -> eyre::Result<()> {
+    let keys = config
+        .uploaders
+        .iter()
+        .flat_map(|uploader| uploader.keys.iter().map(|key| (uploader.name.as_str(), key)))
+        .chain(std::iter::once((uploader::DEFAULT_NAME, &config.uploader_key)));
+
+    for (name, key) in keys.filter(|(_, key)| is_hashed_key(key)) {
+        let hash = PasswordHash::new(key)
+            .map_err(|e| eyre!("hashed key of uploader account `{name}` is not a valid PHC string: {e}"))?;
+
+        // keys are only ever verified with Argon2, so a bcrypt or scrypt hash would never match
+        if argon2::Algorithm::try_from(hash.algorithm).is_err() {
+            bail!(
+                "hashed key of uploader account `{name}` was hashed with `{}`, only Argon2 hashes are supported",
+                hash.algorithm
+            );
+        }
+    }
+
+    Ok(())
+}
+
 macro_rules! impl_enum_based_env_value {
     ($env:expr, {
         on match fail: |$input:ident| $error:literal$( [$($arg:expr),*])?;
@@ -215,4 +397,19 @@ mod tests {
         let config = Config::try_from_env();
         assert!(config.is_ok());
     }
+
+    #[test]
+    fn validate_hashed_keys() {
+        let mut config: Config = toml::from_str(r#"uploader_key = "abc""#).unwrap();
+        assert!(super::validate_hashed_keys(&config).is_ok());
+
+        config.uploader_key = super::hash_key("abc").unwrap();
+        assert!(super::validate_hashed_keys(&config).is_ok());
+
+        // a valid PHC string, but not one that can ever match
+        config.uploader_key = String::from("$scrypt$ln=15,r=8,p=1$c2FsdHNhbHQ$aGFzaGhhc2hoYXNoaGFzaGhhc2g");
+        let err = super::validate_hashed_keys(&config).unwrap_err().to_string();
+        assert!(err.contains("only Argon2"));
+        assert!(!err.contains(&config.uploader_key));
+    }
 }
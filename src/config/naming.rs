@@ -0,0 +1,159 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use azalia::config::{
+    env::{self, TryFromEnv},
+    merge::Merge,
+};
+use serde::{Deserialize, Serialize};
+
+pub const STRATEGY: &str = "UME_NAMING_STRATEGY";
+pub const LENGTH: &str = "UME_NAMING_LENGTH";
+pub const ALPHABET: &str = "UME_NAMING_ALPHABET";
+pub const WORDS: &str = "UME_NAMING_WORDS";
+
+/// ## `[naming]` table
+/// Configures how uploaded images are named. The image's extension is always appended
+/// to the generated name.
+///
+/// ## Example
+/// ```toml
+/// [naming]
+/// strategy = "words"
+/// words = 4
+/// ```
+#[derive(Debug, Clone, Merge, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// How names are generated.
+    #[serde(default)]
+    #[merge(strategy = __merge_strategy)]
+    pub strategy: Strategy,
+
+    /// Length of the names that the `random` strategy generates, and how many hex digits
+    /// of the digest the `content-hash` strategy uses.
+    #[serde(default = "__default_length")]
+    pub length: usize,
+
+    /// Characters that the `random` strategy picks from. This is also used for the random
+    /// suffix that is added when a `timestamp` or `content-hash` name is already taken.
+    #[serde(default = "__default_alphabet")]
+    pub alphabet: String,
+
+    /// How many words the `words` strategy uses.
+    #[serde(default = "__default_words")]
+    pub words: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            strategy: Strategy::default(),
+            length: __default_length(),
+            alphabet: __default_alphabet(),
+            words: __default_words(),
+        }
+    }
+}
+
+impl TryFromEnv for Config {
+    type Error = eyre::Report;
+
+    fn try_from_env() -> Result<Self, Self::Error> {
+        Ok(Config {
+            strategy: crate::config::impl_enum_based_env_value!(STRATEGY, {
+                on match fail: |input| "environment variable `${}` is invalid: expected `random`, `uuidv7`, `timestamp`, `words`, or `content-hash`: received '{}' instead!" [STRATEGY, input];
+
+                "random" | "" => Strategy::Random;
+                "uuidv7" | "uuid" => Strategy::Uuidv7;
+                "timestamp" => Strategy::Timestamp;
+                "words" => Strategy::Words;
+                "content-hash" | "hash" => Strategy::ContentHash;
+            }),
+
+            length: env::try_parse_or_else(LENGTH, __default_length())?,
+            alphabet: env::try_parse_or_else(ALPHABET, __default_alphabet())?,
+            words: env::try_parse_or_else(WORDS, __default_words())?,
+        })
+    }
+}
+
+impl Config {
+    pub(crate) fn validate(&self) -> eyre::Result<()> {
+        if self.length == 0 {
+            bail!("`naming.length` must be greater than zero");
+        }
+
+        if self.strategy == Strategy::ContentHash && self.length > 64 {
+            bail!("`naming.length` can't be longer than 64 hex digits with the `content-hash` strategy");
+        }
+
+        // names end up in URLs and storage paths, so they can't have anything that would
+        // need to be escaped or that could make a path like `..`
+        if self.alphabet.is_empty()
+            || !self
+                .alphabet
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
+        {
+            bail!("`naming.alphabet` must only have ASCII letters, digits, `-` or `_`");
+        }
+
+        if self.words == 0 {
+            bail!("`naming.words` must be greater than zero");
+        }
+
+        Ok(())
+    }
+}
+
+/// How uploaded images are named.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Random characters from the configured alphabet, like `aB3xYz`.
+    #[default]
+    Random,
+
+    /// A UUIDv7, which sorts by when the image was uploaded.
+    Uuidv7,
+
+    /// When the image was uploaded, like `20250101-120000`.
+    Timestamp,
+
+    /// Random words, like `happy-purple-otter`.
+    Words,
+
+    /// The start of the hex-encoded SHA-256 digest of the image's contents.
+    ContentHash,
+}
+
+const fn __default_length() -> usize {
+    6
+}
+
+fn __default_alphabet() -> String {
+    String::from("ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789")
+}
+
+const fn __default_words() -> usize {
+    3
+}
+
+fn __merge_strategy(me: &mut Strategy, other: Strategy) {
+    if *me != other {
+        *me = other;
+    }
+}
@@ -0,0 +1,216 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use etcetera::{BaseStrategy, base_strategy::choose_native_strategy};
+use eyre::Context;
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Value that was loaded from, or generated for, a state file.
+pub struct State {
+    pub value: String,
+
+    /// Whether the value was generated because no state file had one.
+    pub generated: bool,
+
+    /// Where the value is kept. This is `None` if it was generated but couldn't be written
+    /// anywhere, so it only lives until the server stops.
+    pub path: Option<PathBuf>,
+}
+
+/// Returns the directories where the server keeps state that it generated itself, like
+/// the uploader key when none was configured, in the order that they're tried.
+///
+/// If `state_dir` is configured, then that is the only one. Otherwise, this will be the
+/// `.ume` directory in the data directory if the filesystem storage service is used, or
+/// the directory that the configuration file is in (the current directory if there isn't
+/// one), followed by the platform's data directory in case the first one can't be
+/// written to, like when the configuration is mounted read-only.
+pub fn directories(config: &super::Config, config_path: Option<&Path>) -> Vec<PathBuf> {
+    if let Some(ref dir) = config.state_dir {
+        return vec![dir.clone()];
+    }
+
+    let first = match config.storage {
+        super::storage::Config::Filesystem(ref fs) => fs.directory.join(".ume"),
+        _ => config_path
+            .and_then(Path::parent)
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from(".")),
+    };
+
+    let mut directories = vec![first];
+    if let Ok(strategy) = choose_native_strategy() {
+        directories.push(strategy.data_dir().join("Noel/ume"));
+    }
+
+    directories
+}
+
+/// Returns the first of `directories` that can be written to, or the first one if none
+/// of them can.
+pub fn writable(directories: &[PathBuf]) -> PathBuf {
+    directories
+        .iter()
+        .find(|dir| fs::create_dir_all(dir).is_ok() && tempfile::tempfile_in(dir).is_ok())
+        .or_else(|| directories.first())
+        .cloned()
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Loads the value kept in the state file `name` from the first of `directories` that has
+/// it. If none do (or the file is empty), then a value is created with `generate` and
+/// written to the first directory that it can be written to, with only the owner being
+/// able to read it.
+///
+/// A value that can't be written anywhere is still returned, with a warning, so that a
+/// read-only configuration directory doesn't keep the server from starting.
+pub fn load_or_generate<F: FnOnce() -> String>(
+    directories: &[PathBuf],
+    name: &str,
+    generate: F,
+) -> eyre::Result<State> {
+    for path in directories.iter().map(|dir| dir.join(name)) {
+        // a directory that can't be looked in can't have it either
+        if !path.try_exists().unwrap_or(false) {
+            continue;
+        }
+
+        let value =
+            fs::read_to_string(&path).with_context(|| format!("failed to read state file {}", path.display()))?;
+
+        let value = value.trim();
+        if !value.is_empty() {
+            restrict(&path);
+            return Ok(State {
+                value: value.to_owned(),
+                generated: false,
+                path: Some(path),
+            });
+        }
+    }
+
+    let value = generate();
+    for path in directories.iter().map(|dir| dir.join(name)) {
+        match write(&path, &value) {
+            Ok(()) => {
+                return Ok(State {
+                    value,
+                    generated: true,
+                    path: Some(path),
+                });
+            }
+
+            Err(e) => eprintln!("[ume WARN] unable to write state file {}: {e:#}", path.display()),
+        }
+    }
+
+    eprintln!(
+        "[ume WARN] state file `{name}` couldn't be written anywhere, so its value will only last until the server stops. Set `state_dir` to a writable directory to keep it."
+    );
+    Ok(State {
+        value,
+        generated: true,
+        path: None,
+    })
+}
+
+fn write(path: &Path, value: &str) -> eyre::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("failed to create state file {}", path.display()))?;
+
+    // the mode is only applied to files that didn't exist yet
+    restrict(path);
+    writeln!(file, "{value}")?;
+    Ok(())
+}
+
+/// Makes sure that only the owner of the state file at `path` can read it.
+#[cfg(unix)]
+fn restrict(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+
+    let Ok(metadata) = fs::metadata(path) else {
+        return;
+    };
+
+    if metadata.permissions().mode() & 0o077 == 0 {
+        return;
+    }
+
+    if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(0o600)) {
+        eprintln!(
+            "[ume WARN] state file {} can be read by other users and its permissions couldn't be changed: {e}",
+            path.display()
+        );
+    }
+}
+
+#[cfg(not(unix))]
+fn restrict(_: &Path) {}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    #[test]
+    fn load_or_generate() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        // a directory can't be created under a file, like it can't be in a read-only mount
+        let file = tmp.path().join("file");
+        std::fs::write(&file, "").unwrap();
+
+        let directories = [file.join("state"), tmp.path().join("data")];
+        let state = super::load_or_generate(&directories, "key", || String::from("abc")).unwrap();
+        assert!(state.generated);
+        assert_eq!(state.path, Some(tmp.path().join("data/key")));
+
+        let state = super::load_or_generate(&directories, "key", || String::from("def")).unwrap();
+        assert!(!state.generated);
+        assert_eq!(state.value, "abc");
+
+        let state = super::load_or_generate(&[file.join("state")], "key", || String::from("ghi")).unwrap();
+        assert_eq!((state.value.as_str(), state.path), ("ghi", None::<PathBuf>));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let path = tmp.path().join("data/key");
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+            super::load_or_generate(&directories, "key", String::new).unwrap();
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+    }
+}
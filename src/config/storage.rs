@@ -39,7 +39,7 @@ const STORAGE_SERVICE: &str = "UME_STORAGE_SERVICE";
 pub enum Config {
     /// Allows **ume** to connect to an external MongoDB server that allows
     /// GridFS features to be used.
-    Gridfs(remi::gridfs::StorageConfig),
+    Gridfs(Box<remi::gridfs::StorageConfig>),
 
     /// Allows **ume** to use the local filesystem to store images in.
     Filesystem(remi::fs::StorageConfig),
@@ -72,7 +72,7 @@ impl TryFromEnv for Config {
             }));
 
             "s3" => Ok(Config::S3(s3::create_config()?));
-            "gridfs" => Ok(Config::Gridfs(gridfs::create_config()?));
+            "gridfs" => Ok(Config::Gridfs(Box::new(gridfs::create_config()?)));
             "azure" => Ok(Config::Azure(azure::create_config()?));
         })
     }
@@ -86,7 +86,7 @@ impl Merge for Config {
             }
 
             (Self::Gridfs(me), Self::Gridfs(other)) => {
-                gridfs::merge_config(me, other);
+                gridfs::merge_config(me, *other);
             }
 
             (Self::Azure(me), Self::Azure(other)) => {
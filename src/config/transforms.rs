@@ -0,0 +1,149 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config::util;
+use azalia::config::{
+    env::{self, TryFromEnv},
+    merge::Merge,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub const REQUIRE_SIGNATURE: &str = "UME_TRANSFORMS_REQUIRE_SIGNATURE";
+pub const MAX_DIMENSION: &str = "UME_TRANSFORMS_MAX_DIMENSION";
+pub const MAX_CONCURRENCY: &str = "UME_TRANSFORMS_MAX_CONCURRENCY";
+
+/// ## `[transforms]` table
+/// Configures how images can be transformed when they're requested with the `w`, `h`, `fit`,
+/// `format`, or `q` query parameters.
+///
+/// ## Example
+/// ```toml
+/// [transforms]
+/// require_signature = true
+///
+/// [transforms.presets.preview]
+/// w = 640
+/// format = "webp"
+/// ```
+#[derive(Debug, Clone, Merge, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Whether if transformations require a signature that was minted with the
+    /// `POST /images/{name}/sign` endpoint. Presets can always be used without one.
+    #[serde(default)]
+    #[merge(strategy = azalia::config::merge::strategy::bool::only_if_falsy)]
+    pub require_signature: bool,
+
+    /// The largest width or height that an image can be transformed into.
+    #[serde(default = "__default_max_dimension")]
+    pub max_dimension: u32,
+
+    /// How many images can be transformed at the same time. By default, this is the
+    /// amount of CPU cores available.
+    #[serde(default = "__default_max_concurrency")]
+    pub max_concurrency: usize,
+
+    /// Named transformations that can be used with the `preset` query parameter.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub presets: BTreeMap<String, Transform>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            require_signature: false,
+            max_dimension: __default_max_dimension(),
+            max_concurrency: __default_max_concurrency(),
+            presets: BTreeMap::new(),
+        }
+    }
+}
+
+impl TryFromEnv for Config {
+    type Error = eyre::Report;
+
+    fn try_from_env() -> Result<Self, Self::Error> {
+        Ok(Config {
+            require_signature: util::bool_env(REQUIRE_SIGNATURE)?,
+            max_dimension: env::try_parse_or_else(MAX_DIMENSION, __default_max_dimension())?,
+            max_concurrency: env::try_parse_or_else(MAX_CONCURRENCY, __default_max_concurrency())?,
+            presets: BTreeMap::new(),
+        })
+    }
+}
+
+/// A transformation that can be applied to an image. Every field is optional, so an
+/// empty transformation returns the image as-is.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Transform {
+    /// Width to resize to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub w: Option<u32>,
+
+    /// Height to resize to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub h: Option<u32>,
+
+    /// How to resize when both `w` and `h` are given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fit: Option<Fit>,
+
+    /// Format to encode the image as. By default, the image keeps its format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<Format>,
+
+    /// Quality (1-100) to encode lossy formats with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub q: Option<u8>,
+}
+
+/// How an image is resized when both a width and height are given.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Scales the image down to fit within the width and height while keeping its
+    /// aspect ratio.
+    #[default]
+    Contain,
+
+    /// Scales the image to fill the width and height while keeping its aspect ratio,
+    /// cropping whatever doesn't fit.
+    Cover,
+
+    /// Stretches the image to the width and height.
+    Fill,
+}
+
+/// Format that an image can be transformed into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Png,
+
+    #[serde(alias = "jpg")]
+    Jpeg,
+
+    Webp,
+}
+
+const fn __default_max_dimension() -> u32 {
+    4096
+}
+
+fn __default_max_concurrency() -> usize {
+    num_cpus::get()
+}
@@ -0,0 +1,211 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+/// Name of the account that authenticates with `uploader_key`, which uploader accounts
+/// can't have so that they're never mistaken for it.
+pub const DEFAULT_NAME: &str = "default";
+
+/// ## `[[uploaders]]` table
+/// Configures a named uploader account. Each account can hold multiple keys, so revoking
+/// one person's key doesn't require rotating it for everyone else.
+///
+/// ## Example
+/// ```toml
+/// [[uploaders]]
+/// name = "noel"
+/// keys = ["a-very-secret-key"]
+/// scopes = ["upload", "delete"]
+/// ```
+///
+/// Uploaders can also be keyed by their name:
+///
+/// ```hcl
+/// uploader "noel" {
+///   keys = ["a-very-secret-key"]
+/// }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Name of the account. This is what shows up in logs, traces and Sentry events
+    /// when the account does something. It can't be `default`, which is the name of the
+    /// account that authenticates with `uploader_key`.
+    #[serde(default)]
+    pub name: String,
+
+    /// List of keys that can authenticate as this account.
+    #[serde(default, alias = "key", deserialize_with = "__one_or_many")]
+    pub keys: Vec<String>,
+
+    /// What this account is allowed to do. By default, an account can upload, delete
+    /// and list images.
+    #[serde(default = "__default_scopes")]
+    pub scopes: Vec<Scope>,
+}
+
+impl Config {
+    /// Returns `true` if this account was granted `scope`. The [`Scope::Admin`] scope
+    /// implies every other scope.
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.iter().any(|s| *s == scope || *s == Scope::Admin)
+    }
+}
+
+/// A permission that can be granted to an uploader account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Allows uploading images.
+    Upload,
+
+    /// Allows deleting images without their deletion token.
+    Delete,
+
+    /// Allows listing all images.
+    List,
+
+    /// Allows choosing the name of uploaded images, instead of having one generated.
+    Name,
+
+    /// Allows everything.
+    Admin,
+}
+
+/// Deserializes the uploaders table, which can either be a list of tables with a `name` key
+/// or a map of tables keyed by the uploader's name.
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Config>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        List(Vec<Config>),
+        Named(BTreeMap<String, Config>),
+    }
+
+    Ok(match Repr::deserialize(deserializer)? {
+        Repr::List(list) => list,
+        Repr::Named(named) => named
+            .into_iter()
+            .map(|(name, config)| Config { name, ..config })
+            .collect(),
+    })
+}
+
+/// Validates that every uploader has an unique name and at least one key.
+pub(crate) fn validate(uploaders: &[Config]) -> eyre::Result<()> {
+    let mut names = HashSet::new();
+    for uploader in uploaders {
+        if uploader.name.is_empty() {
+            bail!("uploader accounts are required to have a `name`");
+        }
+
+        if uploader.name == DEFAULT_NAME {
+            bail!("uploader accounts can't be called `{DEFAULT_NAME}`, that's the `uploader_key` account");
+        }
+
+        if !names.insert(uploader.name.as_str()) {
+            bail!("uploader account `{}` was defined more than once", uploader.name);
+        }
+
+        if uploader.keys.iter().all(String::is_empty) {
+            bail!("uploader account `{}` doesn't have any keys", uploader.name);
+        }
+    }
+
+    Ok(())
+}
+
+fn __one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(key) => vec![key],
+        OneOrMany::Many(keys) => keys,
+    })
+}
+
+fn __default_scopes() -> Vec<Scope> {
+    vec![Scope::Upload, Scope::Delete, Scope::List]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scope;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Wrapper {
+        #[serde(alias = "uploader", deserialize_with = "super::deserialize")]
+        uploaders: Vec<super::Config>,
+    }
+
+    #[test]
+    fn deserialize_list() {
+        let wrapper: Wrapper = toml::from_str(
+            r#"
+            [[uploaders]]
+            name = "noel"
+            key = "a"
+
+            [[uploaders]]
+            name = "ice"
+            keys = ["b", "c"]
+            scopes = ["admin"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(wrapper.uploaders.len(), 2);
+        assert_eq!(wrapper.uploaders[0].keys, vec!["a"]);
+        assert!(wrapper.uploaders[0].has_scope(Scope::Upload));
+        assert!(!wrapper.uploaders[0].has_scope(Scope::Admin));
+        assert!(wrapper.uploaders[1].has_scope(Scope::List));
+    }
+
+    #[test]
+    fn deserialize_named() {
+        let wrapper: Wrapper = toml::from_str(
+            r#"
+            [uploader.noel]
+            keys = ["a"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(wrapper.uploaders.len(), 1);
+        assert_eq!(wrapper.uploaders[0].name, "noel");
+        assert!(super::validate(&wrapper.uploaders).is_ok());
+    }
+
+    #[test]
+    fn default_name_is_reserved() {
+        let wrapper: Wrapper = toml::from_str(
+            r#"
+            [uploader.default]
+            keys = ["a"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(super::validate(&wrapper.uploaders).is_err());
+    }
+}
@@ -0,0 +1,287 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use azalia::config::{
+    env::{self, TryFromEnv},
+    merge::Merge,
+};
+use crate::{config::util, format::ImageFormat};
+use charted_core::serde::Duration;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+pub const DEDUPLICATE: &str = "UME_UPLOADS_DEDUPLICATE";
+pub const THUMBNAILS: &str = "UME_UPLOADS_THUMBNAILS";
+pub const FORMATS: &str = "UME_UPLOADS_FORMATS";
+pub const STRIP_METADATA: &str = "UME_UPLOADS_STRIP_METADATA";
+pub const SVG: &str = "UME_UPLOADS_SVG";
+pub const MAX_SVG_SIZE: &str = "UME_UPLOADS_MAX_SVG_SIZE";
+pub const MAX_THUMBNAIL_SOURCE_SIZE: &str = "UME_UPLOADS_MAX_THUMBNAIL_SOURCE_SIZE";
+pub const PREVIEW_VIEWS: &str = "UME_UPLOADS_PREVIEW_VIEWS";
+pub const DEFAULT_EXPIRY: &str = "UME_UPLOADS_DEFAULT_EXPIRY";
+pub const MAX_EXPIRY: &str = "UME_UPLOADS_MAX_EXPIRY";
+
+/// ## `[uploads]` table
+/// Configures how uploaded images are handled.
+#[derive(Debug, Clone, Merge, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// What to do when an image is uploaded that is identical to one that already exists.
+    #[serde(default)]
+    #[merge(strategy = __merge_deduplicate)]
+    pub deduplicate: Deduplicate,
+
+    /// Sizes (in pixels) of the thumbnails that are generated for every upload. Thumbnails
+    /// fit within a square of the given size. The first size is the one that the upload
+    /// response links to.
+    #[serde(default = "__default_thumbnails")]
+    #[merge(strategy = __merge_thumbnails)]
+    pub thumbnails: Vec<u32>,
+
+    /// Largest image (in bytes) that thumbnails are generated for, since the whole image
+    /// has to be decoded into memory to generate them. Larger images don't get any, the
+    /// original is served instead.
+    #[serde(default = "__default_max_thumbnail_source_size")]
+    pub max_thumbnail_source_size: u64,
+
+    /// Image formats that can be uploaded. By default, every format that **ume** knows
+    /// of can be uploaded.
+    #[serde(default = "__default_formats")]
+    #[merge(strategy = __merge_formats)]
+    pub formats: Vec<ImageFormat>,
+
+    /// Whether EXIF (including GPS coordinates), XMP, IPTC and comments are removed from
+    /// uploaded JPEG, PNG and WebP images. The EXIF orientation is applied to the image
+    /// before it is removed, so the image is still displayed the right way up.
+    #[serde(default = "__default_strip_metadata")]
+    #[merge(strategy = __merge_strip_metadata)]
+    pub strip_metadata: bool,
+
+    /// What to do with uploaded SVG images.
+    #[serde(default)]
+    #[merge(strategy = __merge_svg)]
+    pub svg: Svg,
+
+    /// Largest SVG image (in bytes) that can be uploaded. SVG images are sanitized (or
+    /// rasterized) in memory, so they're limited separately from `server.body_limit`.
+    #[serde(default = "__default_max_svg_size")]
+    pub max_svg_size: usize,
+
+    /// How many times bots that show a preview of links (like Discord's or Slack's) can
+    /// fetch an image with a view limit without using up one of its views. Anything can
+    /// claim to be one of those bots, so the allowance is bounded; set it to `0` to count
+    /// them as regular views.
+    #[serde(default = "__default_preview_views")]
+    pub preview_views: u64,
+
+    /// How long images live for when the uploader doesn't say (with the `X-Ume-Expires`
+    /// header or the `expires` form field). Images live forever by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[merge(strategy = __merge_expiry)]
+    pub default_expiry: Option<Duration>,
+
+    /// Longest that images can live for. If this is set, then images that would otherwise
+    /// live forever expire after this long.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[merge(strategy = __merge_expiry)]
+    pub max_expiry: Option<Duration>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            deduplicate: Deduplicate::default(),
+            thumbnails: __default_thumbnails(),
+            max_thumbnail_source_size: __default_max_thumbnail_source_size(),
+            formats: __default_formats(),
+            strip_metadata: __default_strip_metadata(),
+            svg: Svg::default(),
+            max_svg_size: __default_max_svg_size(),
+            preview_views: __default_preview_views(),
+            default_expiry: None,
+            max_expiry: None,
+        }
+    }
+}
+
+impl TryFromEnv for Config {
+    type Error = eyre::Report;
+
+    fn try_from_env() -> Result<Self, Self::Error> {
+        Ok(Config {
+            deduplicate: crate::config::impl_enum_based_env_value!(DEDUPLICATE, {
+                on match fail: |input| "environment variable `${}` is invalid: expected `off`, `reuse`, or `alias`: received '{}' instead!" [DEDUPLICATE, input];
+
+                "off" | "" => Deduplicate::Off;
+                "reuse" => Deduplicate::Reuse;
+                "alias" => Deduplicate::Alias;
+            }),
+
+            thumbnails: env::try_parse_or(THUMBNAILS, __default_thumbnails)?,
+            max_thumbnail_source_size: env::try_parse_or_else(
+                MAX_THUMBNAIL_SOURCE_SIZE,
+                __default_max_thumbnail_source_size(),
+            )?,
+
+            formats: env::try_parse_or(FORMATS, __default_formats)?,
+            strip_metadata: util::env_from_result(
+                std::env::var(STRIP_METADATA).map(|x| azalia::TRUTHY_REGEX.is_match(&x)),
+                __default_strip_metadata(),
+            )?,
+
+            svg: crate::config::impl_enum_based_env_value!(SVG, {
+                on match fail: |input| "environment variable `${}` is invalid: expected `sanitize` or `rasterize`: received '{}' instead!" [SVG, input];
+
+                "sanitize" | "" => Svg::Sanitize;
+                "rasterize" => Svg::Rasterize;
+            }),
+
+            max_svg_size: env::try_parse_or_else(MAX_SVG_SIZE, __default_max_svg_size())?,
+            preview_views: env::try_parse_or_else(PREVIEW_VIEWS, __default_preview_views())?,
+
+            default_expiry: parse_expiry(DEFAULT_EXPIRY)?,
+            max_expiry: parse_expiry(MAX_EXPIRY)?,
+        })
+    }
+}
+
+impl Config {
+    pub(crate) fn validate(&self) -> eyre::Result<()> {
+        if self.thumbnails.contains(&0) {
+            bail!("thumbnail sizes must be greater than zero");
+        }
+
+        if self.max_svg_size == 0 {
+            bail!("`uploads.max_svg_size` must be greater than zero");
+        }
+
+        if self.formats.is_empty() {
+            bail!("at least one image format has to be allowed to be uploaded");
+        }
+
+        let default_expiry = self.default_expiry.map(std::time::Duration::from);
+        let max_expiry = self.max_expiry.map(std::time::Duration::from);
+        if default_expiry.is_some_and(|expiry| expiry.is_zero()) || max_expiry.is_some_and(|expiry| expiry.is_zero()) {
+            bail!("`uploads.default_expiry` and `uploads.max_expiry` must be longer than zero");
+        }
+
+        if let (Some(default), Some(max)) = (default_expiry, max_expiry)
+            && default > max
+        {
+            bail!("`uploads.default_expiry` can't be longer than `uploads.max_expiry`");
+        }
+
+        Ok(())
+    }
+}
+
+/// Deduplication mode. Images are compared by the SHA-256 digest of their contents.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Deduplicate {
+    /// Every upload is stored, even if it is identical to an existing image.
+    #[default]
+    Off,
+
+    /// Responds with the URL of the existing image instead of storing the upload, if the
+    /// existing image was uploaded by the same uploader.
+    Reuse,
+
+    /// Gives the upload a new name that points to the existing image's data, so it
+    /// gets its own deletion token without storing the data twice.
+    Alias,
+}
+
+/// How uploaded SVG images are handled. SVG images can contain scripts that would run
+/// in the same origin as **ume** when they're viewed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Svg {
+    /// Keeps only the SVG elements and attributes that can't run scripts or reference
+    /// anything outside of the image, and keeps it as a SVG.
+    #[default]
+    Sanitize,
+
+    /// Renders the image as a PNG image.
+    Rasterize,
+}
+
+const fn __default_preview_views() -> u64 {
+    3
+}
+
+const fn __default_max_svg_size() -> usize {
+    1024 * 1024
+}
+
+fn parse_expiry(key: &str) -> eyre::Result<Option<Duration>> {
+    match env::try_parse_optional::<_, String>(key) {
+        Ok(Some(value)) => Ok(Some(Duration::from_str(&value)?)),
+        Ok(None) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn __default_thumbnails() -> Vec<u32> {
+    vec![256]
+}
+
+fn __merge_thumbnails(me: &mut Vec<u32>, other: Vec<u32>) {
+    if *me != other {
+        *me = other;
+    }
+}
+
+const fn __default_max_thumbnail_source_size() -> u64 {
+    25 * 1024 * 1024
+}
+
+fn __default_formats() -> Vec<ImageFormat> {
+    ImageFormat::ALL.to_vec()
+}
+
+fn __merge_formats(me: &mut Vec<ImageFormat>, other: Vec<ImageFormat>) {
+    if *me != other {
+        *me = other;
+    }
+}
+
+const fn __default_strip_metadata() -> bool {
+    true
+}
+
+fn __merge_strip_metadata(me: &mut bool, other: bool) {
+    if *me != other {
+        *me = other;
+    }
+}
+
+fn __merge_deduplicate(me: &mut Deduplicate, other: Deduplicate) {
+    if *me != other {
+        *me = other;
+    }
+}
+
+fn __merge_svg(me: &mut Svg, other: Svg) {
+    if *me != other {
+        *me = other;
+    }
+}
+
+fn __merge_expiry(me: &mut Option<Duration>, other: Option<Duration>) {
+    if other.is_some() {
+        *me = other;
+    }
+}
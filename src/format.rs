@@ -0,0 +1,274 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use azalia::config::env::TryFromEnvValue;
+use quick_xml::{
+    NsReader,
+    events::Event,
+    name::{Namespace, ResolveResult},
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, str::FromStr};
+
+/// Namespace that every element of a SVG image is in.
+pub const SVG_NAMESPACE: &[u8] = b"http://www.w3.org/2000/svg";
+
+/// An image format that **ume** accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Png,
+
+    #[serde(alias = "jpg")]
+    Jpeg,
+
+    Gif,
+    Svg,
+    Webp,
+    Avif,
+    Bmp,
+
+    #[serde(alias = "tif")]
+    Tiff,
+
+    Ico,
+    Jxl,
+}
+
+impl ImageFormat {
+    /// Every format that **ume** accepts.
+    pub const ALL: &[ImageFormat] = &[
+        ImageFormat::Png,
+        ImageFormat::Jpeg,
+        ImageFormat::Gif,
+        ImageFormat::Svg,
+        ImageFormat::Webp,
+        ImageFormat::Avif,
+        ImageFormat::Bmp,
+        ImageFormat::Tiff,
+        ImageFormat::Ico,
+        ImageFormat::Jxl,
+    ];
+
+    /// How many bytes from the start of an image [`ImageFormat::sniff`] needs to detect
+    /// its format.
+    pub const SNIFF_LENGTH: usize = 4096;
+
+    /// Detects the format of `data` from its contents rather than trusting what the
+    /// client said it was.
+    pub fn sniff(data: &[u8]) -> Option<ImageFormat> {
+        match data {
+            [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some(ImageFormat::Png),
+            [0xFF, 0xD8, 0xFF, ..] => Some(ImageFormat::Jpeg),
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(ImageFormat::Gif),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(ImageFormat::Webp),
+            [b'I', b'I', 0x2A, 0x00, ..] | [b'M', b'M', 0x00, 0x2A, ..] => Some(ImageFormat::Tiff),
+            [0x00, 0x00, 0x01, 0x00, ..] => Some(ImageFormat::Ico),
+            [0xFF, 0x0A, ..]
+            | [
+                0x00,
+                0x00,
+                0x00,
+                0x0C,
+                b'J',
+                b'X',
+                b'L',
+                b' ',
+                0x0D,
+                0x0A,
+                0x87,
+                0x0A,
+                ..,
+            ] => Some(ImageFormat::Jxl),
+
+            [b'B', b'M', ..] if data.len() >= 26 => Some(ImageFormat::Bmp),
+            [_, _, _, _, b'f', b't', b'y', b'p', ..] if is_avif(data) => Some(ImageFormat::Avif),
+            _ if is_svg(data) => Some(ImageFormat::Svg),
+            _ => None,
+        }
+    }
+
+    /// Returns the format that the content type `ct` refers to.
+    pub fn from_content_type(ct: &str) -> Option<ImageFormat> {
+        let essence = ct.split(';').next().unwrap_or_default().trim();
+        ImageFormat::ALL
+            .iter()
+            .copied()
+            .find(|format| format.content_type().eq_ignore_ascii_case(essence))
+    }
+
+    /// Returns the file extension for this format, without the leading dot.
+    pub const fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Gif => "gif",
+            ImageFormat::Svg => "svg",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Avif => "avif",
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Tiff => "tiff",
+            ImageFormat::Ico => "ico",
+            ImageFormat::Jxl => "jxl",
+        }
+    }
+
+    /// Returns the content type for this format.
+    pub const fn content_type(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Gif => "image/gif",
+            ImageFormat::Svg => "image/svg+xml",
+            ImageFormat::Webp => "image/webp",
+            ImageFormat::Avif => "image/avif",
+            ImageFormat::Bmp => "image/bmp",
+            ImageFormat::Tiff => "image/tiff",
+            ImageFormat::Ico => "image/vnd.microsoft.icon",
+            ImageFormat::Jxl => "image/jxl",
+        }
+    }
+}
+
+impl Display for ImageFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ImageFormat::Jpeg => "jpeg",
+            ImageFormat::Tiff => "tiff",
+            format => format.extension(),
+        })
+    }
+}
+
+/// Error that is returned when parsing an image format that **ume** doesn't know of.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownFormat(String);
+
+impl Display for UnknownFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown image format `{}`", self.0)
+    }
+}
+
+impl std::error::Error for UnknownFormat {}
+
+impl FromStr for ImageFormat {
+    type Err = UnknownFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &*s.trim().to_ascii_lowercase() {
+            "jpg" => Ok(ImageFormat::Jpeg),
+            "tif" => Ok(ImageFormat::Tiff),
+            input => ImageFormat::ALL
+                .iter()
+                .copied()
+                .find(|format| format.to_string() == input)
+                .ok_or_else(|| UnknownFormat(input.to_owned())),
+        }
+    }
+}
+
+impl TryFromEnvValue for ImageFormat {
+    type Error = UnknownFormat;
+
+    fn try_from_env_value(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// AVIF images are ISOBMFF files whose `ftyp` box lists `avif` or `avis` as
+/// a brand.
+fn is_avif(data: &[u8]) -> bool {
+    let size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    let Some(ftyp) = data.get(8..size.min(data.len())) else {
+        return false;
+    };
+
+    // major brand, minor version, and then the compatible brands
+    ftyp.chunks_exact(4)
+        .enumerate()
+        .filter(|(idx, _)| *idx != 1)
+        .any(|(_, brand)| brand == b"avif" || brand == b"avis")
+}
+
+/// SVG images are XML documents whose root element is a `<svg>` element in the SVG
+/// namespace, so look for it near the start of the document. Anything else (like a XHTML
+/// document with a `<svg>` element inside of it) would be rendered as something else.
+fn is_svg(data: &[u8]) -> bool {
+    let head = &data[..data.len().min(ImageFormat::SNIFF_LENGTH)];
+    let mut reader = NsReader::from_reader(head.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(head));
+
+    loop {
+        match reader.read_resolved_event() {
+            Ok((namespace, Event::Start(element) | Event::Empty(element))) => {
+                return namespace == ResolveResult::Bound(Namespace(SVG_NAMESPACE))
+                    && element.local_name().as_ref() == b"svg";
+            }
+
+            Ok((_, Event::Decl(_) | Event::Comment(_) | Event::PI(_) | Event::DocType(_))) => {}
+            Ok((_, Event::Text(text))) if text.iter().all(u8::is_ascii_whitespace) => {}
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ImageFormat;
+
+    #[test]
+    fn sniff() {
+        assert_eq!(
+            ImageFormat::sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+            Some(ImageFormat::Png)
+        );
+
+        assert_eq!(ImageFormat::sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some(ImageFormat::Webp));
+        assert_eq!(
+            ImageFormat::sniff(b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf"),
+            Some(ImageFormat::Avif)
+        );
+
+        assert_eq!(ImageFormat::sniff(b"\0\0\0\x18ftypheic\0\0\0\0mif1heic"), None);
+
+        assert_eq!(
+            ImageFormat::sniff(br#"<?xml version="1.0"?><svg xmlns="http://www.w3.org/2000/svg"></svg>"#),
+            Some(ImageFormat::Svg)
+        );
+
+        // the root element has to be a `<svg>` element in the SVG namespace
+        assert_eq!(
+            ImageFormat::sniff(
+                br#"<html xmlns="http://www.w3.org/1999/xhtml"><svg xmlns="http://www.w3.org/2000/svg"/></html>"#
+            ),
+            None
+        );
+
+        assert_eq!(ImageFormat::sniff(b"<svg></svg>"), None);
+        assert_eq!(
+            ImageFormat::sniff(br#"<s:svg xmlns:s="http://www.w3.org/2000/svg"/>"#),
+            Some(ImageFormat::Svg)
+        );
+
+        assert_eq!(ImageFormat::sniff(b"hello world"), None);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("jpg".parse(), Ok(ImageFormat::Jpeg));
+        assert_eq!("WEBP".parse(), Ok(ImageFormat::Webp));
+        assert!("heic".parse::<ImageFormat>().is_err());
+    }
+}
@@ -23,6 +23,7 @@ extern crate eyre;
 
 pub mod cli;
 pub mod config;
+pub mod format;
 pub mod server;
 
 /// Constant that refers to the version of the Rust compiler that was used. This is mainly
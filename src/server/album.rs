@@ -0,0 +1,74 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::metadata::{Album, AlbumImage};
+use axum::{
+    http::{HeaderValue, header},
+    response::{Html, IntoResponse, Response},
+};
+use quick_xml::escape::escape;
+use std::fmt::Write as _;
+
+/// Renders the page of `album`, which shows `images` in order with their captions.
+pub fn render(config: &crate::config::Config, album: &Album, images: &[&AlbumImage]) -> Response {
+    let title = escape(album.title.as_deref().unwrap_or("Album"));
+
+    let mut figures = String::new();
+    for image in images {
+        let src = escape(format!("{}images/{}", config.base_url, image.name));
+        let caption = image.caption.as_deref().map(escape);
+
+        let _ = write!(
+            figures,
+            r#"<figure><a href="{src}"><img src="{src}" alt="{}" loading="lazy"></a>"#,
+            caption.as_deref().unwrap_or_default()
+        );
+
+        if let Some(caption) = caption {
+            let _ = write!(figures, "<figcaption>{caption}</figcaption>");
+        }
+
+        figures.push_str("</figure>\n");
+    }
+
+    // images are the only thing that the page loads, and only from this server
+    let csp = format!(
+        "default-src 'none'; img-src {}; style-src 'unsafe-inline'",
+        config.base_url.origin().ascii_serialization()
+    );
+
+    (
+        [(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_str(&csp).unwrap_or(HeaderValue::from_static("default-src 'none'")),
+        )],
+        Html(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<style>body{{font-family:sans-serif;max-width:960px;margin:2rem auto;padding:0 1rem}}figure{{margin:0 0 2rem}}img{{max-width:100%}}figcaption{{margin-top:.5rem;color:#555}}</style>
+</head>
+<body>
+<h1>{title}</h1>
+{figures}</body>
+</html>
+"#
+        )),
+    )
+        .into_response()
+}
@@ -0,0 +1,411 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::transform;
+use crate::config::{is_hashed_key, uploader::Scope};
+use axum::{
+    Json,
+    extract::{FromRequestParts, OptionalFromRequestParts},
+    http::{
+        HeaderValue, StatusCode,
+        header::{self, AUTHORIZATION},
+        request::Parts,
+    },
+    response::{IntoResponse, Response},
+};
+use axum_extra::headers::{Header, HeaderMapExt};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+use subtle::ConstantTimeEq;
+use tokio::sync::Semaphore;
+
+/// Typed `Authorization` header that holds an uploader key. The following forms are
+/// accepted:
+///
+/// * `Uploader <key>` (what `ume sharex` generates)
+/// * `Bearer <key>`
+/// * `Basic <base64(name:key)>`, where the key is the password
+/// * `<key>`
+pub struct UploaderKey(pub String);
+impl Header for UploaderKey {
+    fn name() -> &'static axum::http::HeaderName {
+        &AUTHORIZATION
+    }
+
+    fn encode<E: Extend<axum::http::HeaderValue>>(&self, values: &mut E) {
+        values.extend(std::iter::once(
+            HeaderValue::from_str(&format!("Bearer {}", self.0)).unwrap(),
+        ));
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, axum_extra::headers::Error>
+    where
+        Self: Sized,
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        values
+            .next()
+            .and_then(|h| h.to_str().ok())
+            .and_then(parse_authorization)
+            .map(UploaderKey)
+            .ok_or_else(axum_extra::headers::Error::invalid)
+    }
+}
+
+const SCHEMES: &[&str] = &["uploader", "bearer", "basic"];
+
+/// Extracts the uploader key from the value of an `Authorization` header.
+fn parse_authorization(value: &str) -> Option<String> {
+    let value = value.trim();
+    let (scheme, rest) = match value.split_once(' ') {
+        Some((scheme, rest)) => (scheme, rest.trim()),
+        None if value.is_empty() || SCHEMES.iter().any(|s| s.eq_ignore_ascii_case(value)) => return None,
+        None => return Some(value.to_owned()),
+    };
+
+    if scheme.eq_ignore_ascii_case("uploader") || scheme.eq_ignore_ascii_case("bearer") {
+        return (!rest.is_empty()).then(|| rest.to_owned());
+    }
+
+    if scheme.eq_ignore_ascii_case("basic") {
+        let decoded = BASE64_STANDARD.decode(rest).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+
+        // `curl -u <key>:` puts the key in the username instead
+        return match decoded.split_once(':') {
+            Some((username, "")) if !username.is_empty() => Some(username.to_owned()),
+            Some((_, password)) if !password.is_empty() => Some(password.to_owned()),
+            _ => None,
+        };
+    }
+
+    // not a scheme we know of, so the whole value is the key
+    Some(value.to_owned())
+}
+
+/// Rejects the request with `401 Unauthorized` and a `WWW-Authenticate` challenge
+/// for the schemes that [`UploaderKey`] understands.
+pub fn unauthorized(message: &str) -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(
+            header::WWW_AUTHENTICATE,
+            HeaderValue::from_static(r#"Bearer realm="ume", Basic realm="ume", charset="UTF-8""#),
+        )],
+        Json(json!({
+            "message": message
+        })),
+    )
+        .into_response()
+}
+
+/// Uploader account that was matched from the request's uploader key. Keys are only
+/// matched by routes that extract this, so routes that anyone can use never spend time
+/// verifying keys; the account is then kept in the request's extensions and attached to
+/// the current span and Sentry scope.
+#[derive(Debug, Clone)]
+pub struct Uploader(Arc<crate::config::uploader::Config>);
+
+impl Uploader {
+    /// Name of the uploader account.
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// Rejects with `403 Forbidden` if this account wasn't granted `scope`.
+    pub fn require(&self, scope: Scope) -> Result<(), (StatusCode, Json<Value>)> {
+        if self.0.has_scope(scope) {
+            return Ok(());
+        }
+
+        Err((
+            StatusCode::FORBIDDEN,
+            Json(json!({
+                "message": format!("uploader `{}` is not allowed to do this", self.name())
+            })),
+        ))
+    }
+
+    /// Returns `true` if this account was granted `scope`.
+    pub fn can(&self, scope: Scope) -> bool {
+        self.0.has_scope(scope)
+    }
+}
+
+/// How many keys can be verified against hashed keys at the same time. Argon2 uses a lot
+/// of CPU and memory on purpose, so requests with made up keys can't be allowed to use
+/// as much of either as they want.
+const VERIFY_CONCURRENCY: usize = 4;
+
+/// How many keys can be waiting to be verified (including the ones that are being
+/// verified) before requests are rejected with `503 Service Unavailable`, so that requests
+/// with made up keys can't hold up every other request either.
+const VERIFY_QUEUE: usize = 32;
+
+/// How many keys that matched a hashed key are remembered, so that they don't have to be
+/// verified with Argon2 again.
+const VERIFIED_CAPACITY: usize = 1024;
+
+/// Returned by [`Authenticator::find`] when too many keys are waiting to be verified.
+#[derive(Debug)]
+pub struct Busy;
+
+/// Finds the uploader account that an uploader key belongs to. This is cheap to clone and
+/// is available as an extension.
+#[derive(Clone)]
+pub struct Authenticator(Arc<Keys>);
+
+struct Keys {
+    /// Every configured key, along with the account that it belongs to.
+    keys: Vec<(String, Uploader)>,
+
+    /// Limits how many hashed keys are verified at the same time.
+    pool: transform::Pool,
+
+    /// Limits how many hashed keys can be waiting to be verified.
+    queue: Semaphore,
+
+    /// Accounts of keys that matched a hashed key, by the SHA-256 digest of the key.
+    verified: Mutex<HashMap<[u8; 32], Uploader>>,
+}
+
+impl Authenticator {
+    pub fn new(config: &crate::config::Config) -> Self {
+        let mut keys = Vec::new();
+        for account in &config.uploaders {
+            let uploader = Uploader(Arc::new(account.clone()));
+            keys.extend(account.keys.iter().map(|key| (key.clone(), uploader.clone())));
+        }
+
+        if !config.uploader_key.is_empty() {
+            let uploader = Uploader(Arc::new(crate::config::uploader::Config {
+                name: String::from(crate::config::uploader::DEFAULT_NAME),
+                keys: Vec::new(),
+                scopes: vec![Scope::Admin],
+            }));
+
+            keys.push((config.uploader_key.clone(), uploader));
+        }
+
+        Authenticator(Arc::new(Keys {
+            keys,
+            pool: transform::Pool::new(VERIFY_CONCURRENCY),
+            queue: Semaphore::new(VERIFY_QUEUE),
+            verified: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// Finds the uploader account that `key` belongs to.
+    ///
+    /// Every plaintext key is compared in constant time and that search doesn't stop at
+    /// the first match, so the time it takes doesn't reveal which key (or how much of it)
+    /// matched. Hashed keys are only verified when no plaintext key matched, and stop at
+    /// the first one that does. Returns [`Busy`] if too many keys are already waiting to
+    /// be verified.
+    pub async fn find(&self, key: &str) -> Result<Option<Uploader>, Busy> {
+        let mut found = None;
+        for (candidate, uploader) in self.0.keys.iter().filter(|(candidate, _)| !is_hashed_key(candidate)) {
+            if compare(candidate, key) && found.is_none() {
+                found = Some(uploader.clone());
+            }
+        }
+
+        if found.is_some() || !self.0.keys.iter().any(|(candidate, _)| is_hashed_key(candidate)) {
+            return Ok(found);
+        }
+
+        let digest: [u8; 32] = Sha256::digest(key.as_bytes()).into();
+        if let Some(uploader) = self.verified().get(&digest) {
+            return Ok(Some(uploader.clone()));
+        }
+
+        let _permit = self.0.queue.try_acquire().map_err(|_| Busy)?;
+        let (keys, key) = (self.0.clone(), key.to_owned());
+        let found = self
+            .0
+            .pool
+            .run(move || {
+                keys.keys
+                    .iter()
+                    .filter(|(candidate, _)| is_hashed_key(candidate))
+                    .find(|(candidate, _)| compare(candidate, &key))
+                    .map(|(_, uploader)| uploader.clone())
+            })
+            .await;
+
+        let Some(found) = found else {
+            return Ok(None);
+        };
+
+        let mut verified = self.verified();
+        if verified.len() >= VERIFIED_CAPACITY {
+            verified.clear();
+        }
+
+        verified.insert(digest, found.clone());
+        Ok(Some(found))
+    }
+
+    fn verified(&self) -> MutexGuard<'_, HashMap<[u8; 32], Uploader>> {
+        self.0.verified.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn compare(expected: &str, given: &str) -> bool {
+    if expected.is_empty() {
+        return false;
+    }
+
+    if is_hashed_key(expected) {
+        return super::password::verify(expected, given);
+    }
+
+    bool::from(expected.as_bytes().ct_eq(given.as_bytes()))
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Uploader {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        <Uploader as OptionalFromRequestParts<S>>::from_request_parts(parts, state)
+            .await?
+            .ok_or_else(|| unauthorized("invalid uploader key received"))
+    }
+}
+
+impl<S: Send + Sync> OptionalFromRequestParts<S> for Uploader {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Option<Self>, Self::Rejection> {
+        if let Some(uploader) = parts.extensions.get::<Uploader>() {
+            return Ok(Some(uploader.clone()));
+        }
+
+        let (Some(UploaderKey(key)), Some(authenticator)) = (
+            parts.headers.typed_get::<UploaderKey>(),
+            parts.extensions.get::<Authenticator>().cloned(),
+        ) else {
+            return Ok(None);
+        };
+
+        let Some(uploader) = authenticator.find(&key).await.map_err(|_| busy())? else {
+            return Ok(None);
+        };
+
+        let name = uploader.name().to_owned();
+        tracing::Span::current().record("uploader", name.as_str());
+        sentry::configure_scope(|scope| {
+            scope.set_user(Some(sentry::User {
+                username: Some(name),
+                ..Default::default()
+            }));
+        });
+
+        parts.extensions.insert(uploader.clone());
+        Ok(Some(uploader))
+    }
+}
+
+/// Rejects the request with `503 Service Unavailable` when too many keys are waiting to
+/// be verified.
+fn busy() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, HeaderValue::from_static("1"))],
+        Json(json!({
+            "message": "too many uploader keys are being verified right now, pls try again later"
+        })),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Authenticator, compare, parse_authorization};
+
+    #[test]
+    fn parse_schemes() {
+        assert_eq!(parse_authorization("Uploader abc").as_deref(), Some("abc"));
+        assert_eq!(parse_authorization("uploader abc").as_deref(), Some("abc"));
+        assert_eq!(parse_authorization("Bearer abc").as_deref(), Some("abc"));
+        assert_eq!(parse_authorization("abc").as_deref(), Some("abc"));
+        assert_eq!(parse_authorization("Bearer ").as_deref(), None);
+        assert_eq!(parse_authorization("").as_deref(), None);
+    }
+
+    #[test]
+    fn parse_basic() {
+        // noel:abc
+        assert_eq!(parse_authorization("Basic bm9lbDphYmM=").as_deref(), Some("abc"));
+
+        // abc:
+        assert_eq!(parse_authorization("Basic YWJjOg==").as_deref(), Some("abc"));
+
+        // :
+        assert_eq!(parse_authorization("Basic Og==").as_deref(), None);
+        assert_eq!(parse_authorization("Basic !!!").as_deref(), None);
+    }
+
+    #[test]
+    fn compare_keys() {
+        assert!(compare("abc", "abc"));
+        assert!(!compare("abc", "abd"));
+        assert!(!compare("", ""));
+
+        let hashed = crate::config::hash_key("abc").unwrap();
+        assert!(compare(&hashed, "abc"));
+        assert!(!compare(&hashed, "abd"));
+    }
+
+    #[tokio::test]
+    async fn authenticator() {
+        let hashed = crate::config::hash_key("hashed").unwrap();
+        let config: crate::config::Config = toml::from_str(&format!(
+            r#"
+            uploader_key = "plain"
+
+            [[uploaders]]
+            name = "noel"
+            keys = ["{hashed}"]
+            "#
+        ))
+        .unwrap();
+
+        let authenticator = Authenticator::new(&config);
+        let find = async |key| {
+            let found = authenticator.find(key).await;
+            found.map(|found| found.map(|uploader| uploader.name().to_owned()))
+        };
+
+        assert_eq!(find("plain").await.unwrap().as_deref(), Some("default"));
+        assert!(authenticator.find("nope").await.unwrap().is_none());
+
+        assert_eq!(find("hashed").await.unwrap().as_deref(), Some("noel"));
+        assert_eq!(authenticator.verified().len(), 1);
+
+        // keys that have to be verified are turned away once the queue is full, unlike
+        // the ones that are already known
+        let queue = super::VERIFY_QUEUE as u32;
+        let _queue = authenticator.0.queue.try_acquire_many(queue).unwrap();
+        assert!(find("nope").await.is_err());
+        assert_eq!(find("hashed").await.unwrap().as_deref(), Some("noel"));
+        assert_eq!(find("plain").await.unwrap().as_deref(), Some("default"));
+    }
+}
@@ -0,0 +1,119 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::metadata::{self, Image};
+use azalia::remi::{
+    StorageService,
+    core::{Blob, File, ListBlobsRequest, StorageService as _},
+};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use tracing::Instrument;
+
+/// Spawns a task that records the metadata of every image that was uploaded before the
+/// metadata store existed, so that they're listed along with every other image.
+pub fn spawn(storage: StorageService, metadata: metadata::Store) {
+    tokio::spawn(
+        async move {
+            match backfill(&storage, &metadata).await {
+                Ok(0) => {}
+                Ok(recorded) => info!(recorded, "recorded images that were uploaded before metadata was kept"),
+                Err(e) => {
+                    error!(error = %e, "unable to record images that were uploaded before metadata was kept");
+                    sentry::capture_error::<dyn std::error::Error>(e.as_ref());
+                }
+            }
+        }
+        .instrument(info_span!("ume.metadata.backfill")),
+    );
+}
+
+/// Lists every image in the storage service and records the ones that don't have a record
+/// yet. Returns how many images were recorded.
+pub async fn backfill(storage: &StorageService, metadata: &metadata::Store) -> eyre::Result<usize> {
+    // directories have to be included, otherwise the filesystem storage service errors out
+    // on the `.ume` directory; they're filtered out below anyway.
+    let blobs = storage
+        .blobs(
+            Some("./"),
+            Some(ListBlobsRequest {
+                include_dirs: true,
+                ..Default::default()
+            }),
+        )
+        .await?;
+
+    let mut recorded = 0;
+    for blob in blobs {
+        let Blob::File(file) = blob else {
+            continue;
+        };
+
+        let name = file.name.rsplit('/').next().unwrap_or(&file.name).to_owned();
+        if is_internal(&file) || metadata.get(&name).await?.is_some() {
+            continue;
+        }
+
+        let Some(data) = storage.open(format!("./{name}")).await? else {
+            continue;
+        };
+
+        let dimensions = image::ImageReader::new(std::io::Cursor::new(&data))
+            .with_guessed_format()
+            .ok()
+            .and_then(|reader| reader.into_dimensions().ok());
+
+        let uploaded_at = file
+            .created_at
+            .or(file.last_modified_at)
+            .and_then(|millis| i64::try_from(millis).ok())
+            .and_then(DateTime::<Utc>::from_timestamp_millis)
+            .unwrap_or_else(Utc::now);
+
+        let image = Image {
+            name,
+            uploader: None,
+            original_filename: None,
+            uploaded_at,
+            size: data.len() as u64,
+            width: dimensions.map(|(width, _)| width),
+            height: dimensions.map(|(_, height)| height),
+            sha256: format!("{:x}", Sha256::digest(&data)),
+            content_type: file
+                .content_type
+                .unwrap_or_else(|| azalia::remi::fs::default_resolver(&data).to_string()),
+            alias_of: None,
+            expires_at: None,
+            max_views: None,
+            views: 0,
+            preview_views: 0,
+            private: false,
+            password: None,
+        };
+
+        // an image with the same name could've been uploaded since it was listed
+        if metadata.reserve(&image).await? {
+            recorded += 1;
+        }
+    }
+
+    Ok(recorded)
+}
+
+/// Returns `true` if `file` is something that the server keeps for itself, like deletion
+/// tokens, rather than an uploaded image.
+fn is_internal(file: &File) -> bool {
+    file.name.rsplit('/').next().is_none_or(|name| name.starts_with('.')) || file.path.contains(".ume/")
+}
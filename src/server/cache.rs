@@ -0,0 +1,227 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use axum::{
+    extract::FromRequestParts,
+    http::{HeaderMap, HeaderValue, header, request::Parts},
+};
+use axum_extra::headers::{ETag, HeaderMapExt, IfModifiedSince, IfNoneMatch, IfRange, LastModified};
+use sha2::{Digest, Sha256};
+use std::{
+    convert::Infallible,
+    ops::RangeInclusive,
+    time::{Duration, SystemTime},
+};
+
+/// Most ranges that can be requested at once. Requests for more ranges get the whole image.
+const MAX_RANGES: usize = 16;
+
+/// Conditional request headers that browsers and CDNs send to revalidate their copy of
+/// an image, along with the `Range` header.
+pub struct Conditions {
+    if_none_match: Option<IfNoneMatch>,
+    if_modified_since: Option<IfModifiedSince>,
+    if_range: Option<IfRange>,
+    range: Option<String>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Conditions {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(Conditions {
+            if_none_match: parts.headers.typed_get(),
+            if_modified_since: parts.headers.typed_get(),
+            if_range: parts.headers.typed_get(),
+            range: parts
+                .headers
+                .get(header::RANGE)
+                .and_then(|value| value.to_str().ok())
+                .map(ToOwned::to_owned),
+        })
+    }
+}
+
+/// Parts of an image that were requested with the `Range` header.
+pub enum Ranges {
+    /// The whole image, since no ranges were requested or the image was modified since
+    /// the date or entity tag in `If-Range`.
+    All,
+    Partial(Vec<RangeInclusive<u64>>),
+
+    /// Ranges were requested, but none of them overlap with the image.
+    Unsatisfiable,
+}
+
+/// Validators of an image response. Images can't be modified once they're uploaded, so
+/// the entity tag is derived from the SHA-256 digest of the image.
+pub struct Validators {
+    etag: ETag,
+    last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    /// Creates the validators of an image with the SHA-256 digest `sha256`. `params` are the
+    /// transformation that was applied to it, which gives every variant its own entity tag.
+    pub fn new(sha256: &str, params: &[(&str, String)], last_modified: Option<u128>) -> Validators {
+        let tag = if params.is_empty() {
+            sha256.to_owned()
+        } else {
+            let mut hasher = Sha256::new();
+            hasher.update(sha256);
+            for (key, value) in params {
+                hasher.update(format!("&{key}={value}"));
+            }
+
+            format!("{:x}", hasher.finalize())
+        };
+
+        Validators {
+            etag: format!("\"{tag}\"").parse().expect("entity tag to be valid"),
+            last_modified: last_modified
+                .and_then(|millis| u64::try_from(millis).ok())
+                .map(|millis| SystemTime::UNIX_EPOCH + Duration::from_millis(millis)),
+        }
+    }
+
+    /// Returns `true` if the copy that the client has is still up to date. `If-Modified-Since`
+    /// is only used if the client didn't send `If-None-Match`.
+    pub fn not_modified(&self, conditions: &Conditions) -> bool {
+        match (&conditions.if_none_match, &conditions.if_modified_since) {
+            (Some(if_none_match), _) => !if_none_match.precondition_passes(&self.etag),
+            (None, Some(if_modified_since)) => self
+                .last_modified
+                .is_some_and(|last_modified| !if_modified_since.is_modified(last_modified)),
+
+            (None, None) => false,
+        }
+    }
+
+    /// Returns the parts of an image that is `length` bytes long that were requested.
+    pub fn ranges(&self, conditions: &Conditions, length: u64) -> Ranges {
+        let Some(ref range) = conditions.range else {
+            return Ranges::All;
+        };
+
+        if let Some(ref if_range) = conditions.if_range {
+            let last_modified = self.last_modified.map(LastModified::from);
+            if if_range.is_modified(Some(&self.etag), last_modified.as_ref()) {
+                return Ranges::All;
+            }
+        }
+
+        match parse_ranges(range, length) {
+            Some(ranges) if ranges.is_empty() => Ranges::Unsatisfiable,
+            Some(ranges) => Ranges::Partial(ranges),
+            None => Ranges::All,
+        }
+    }
+
+    /// Returns the `ETag`, `Last-Modified` and `Cache-Control` headers.
+    pub fn headers(&self, cache_control: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.typed_insert(self.etag.clone());
+        if let Some(last_modified) = self.last_modified {
+            headers.typed_insert(LastModified::from(last_modified));
+        }
+
+        if !cache_control.is_empty() {
+            headers.insert(
+                header::CACHE_CONTROL,
+                HeaderValue::from_str(cache_control).expect("validated by the configuration"),
+            );
+        }
+
+        headers
+    }
+}
+
+/// Parses the value of a `Range` header, leaving out ranges that start after the end of
+/// the content. Returns `None` if the header is malformed or asks for too many ranges,
+/// since it is ignored in that case.
+fn parse_ranges(value: &str, length: u64) -> Option<Vec<RangeInclusive<u64>>> {
+    let mut ranges = Vec::new();
+    for spec in value.strip_prefix("bytes=")?.split(',') {
+        let (start, end) = spec.trim().split_once('-')?;
+        let range = match (start.trim(), end.trim()) {
+            // the last `suffix` bytes
+            ("", suffix) => {
+                let suffix = suffix.parse::<u64>().ok()?;
+                if suffix == 0 || length == 0 {
+                    continue;
+                }
+
+                length.saturating_sub(suffix)..=length - 1
+            }
+
+            (start, end) => {
+                let start = start.parse::<u64>().ok()?;
+                let end = match end {
+                    "" => u64::MAX,
+                    end => end.parse::<u64>().ok().filter(|end| *end >= start)?,
+                };
+
+                if start >= length {
+                    continue;
+                }
+
+                start..=end.min(length - 1)
+            }
+        };
+
+        ranges.push(range);
+    }
+
+    (ranges.len() <= MAX_RANGES).then_some(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Conditions, Validators, parse_ranges};
+    use axum_extra::headers::{ETag, IfModifiedSince, IfNoneMatch};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn conditions() {
+        let validators = Validators::new("abc", &[], Some(1_700_000_000_000));
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let conditions = |etag: Option<&str>, since: Option<SystemTime>| Conditions {
+            if_none_match: etag.map(|etag| IfNoneMatch::from(etag.parse::<ETag>().unwrap())),
+            if_modified_since: since.map(IfModifiedSince::from),
+            if_range: None,
+            range: None,
+        };
+
+        assert!(validators.not_modified(&conditions(Some("\"abc\""), None)));
+        assert!(!validators.not_modified(&conditions(Some("\"def\""), Some(modified))));
+        assert!(validators.not_modified(&conditions(None, Some(modified))));
+        assert!(!validators.not_modified(&conditions(None, Some(modified - Duration::from_secs(1)))));
+        assert!(!validators.not_modified(&conditions(None, None)));
+
+        let variant = Validators::new("abc", &[("w", "100".to_owned())], None);
+        assert!(!variant.not_modified(&conditions(Some("\"abc\""), None)));
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_ranges("bytes=0-99", 1000), Some(vec![0..=99]));
+        assert_eq!(parse_ranges("bytes=500-, -100", 1000), Some(vec![500..=999, 900..=999]));
+        assert_eq!(parse_ranges("bytes=900-2000", 1000), Some(vec![900..=999]));
+        assert_eq!(parse_ranges("bytes=-2000", 1000), Some(vec![0..=999]));
+        assert_eq!(parse_ranges("bytes=1000-", 1000), Some(vec![]));
+        assert_eq!(parse_ranges("bytes=5-1", 1000), None);
+        assert_eq!(parse_ranges("items=0-1", 1000), None);
+    }
+}
@@ -20,11 +20,14 @@ use azalia::config::{
     env::{self, TryFromEnv},
     merge::Merge,
 };
+use axum::http::HeaderValue;
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 
 pub const HOST: &[&str; 2] = &["UME_SERVER_HOST", "HOST"];
 pub const PORT: &[&str; 2] = &["UME_SERVER_PORT", "PORT"];
+pub const CACHE_CONTROL: &str = "UME_SERVER_CACHE_CONTROL";
+pub const BODY_LIMIT: &str = "UME_SERVER_BODY_LIMIT";
 
 /// ## `[server]` table
 /// This configures the HTTP service that the API server creates.
@@ -39,6 +42,20 @@ pub struct Config {
     #[serde(default = "__default_port")]
     pub port: u16,
 
+    /// `Cache-Control` header that images are served with. Images can't be modified once
+    /// they're uploaded, so they can be cached forever by default. The header isn't sent
+    /// if this is empty.
+    #[serde(default = "__default_cache_control")]
+    pub cache_control: String,
+
+    /// Largest request body (in bytes) that is accepted, which is how large uploaded
+    /// images can be. Uploads are written to a temporary file while they're received, but
+    /// images are still decoded in memory to apply their EXIF orientation and to generate
+    /// thumbnails (up to `uploads.max_thumbnail_source_size`), so raising this also raises
+    /// how much memory an upload can use.
+    #[serde(default = "__default_body_limit")]
+    pub body_limit: usize,
+
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub ssl: Option<ssl::Config>,
 }
@@ -48,6 +65,8 @@ impl Default for Config {
         Self {
             host: __default_host(),
             port: __default_port(),
+            cache_control: __default_cache_control(),
+            body_limit: __default_body_limit(),
             ssl: None,
         }
     }
@@ -57,6 +76,18 @@ impl Config {
     pub fn to_socket_addr(&self) -> SocketAddr {
         format!("{}:{}", self.host, self.port).parse().unwrap()
     }
+
+    pub(crate) fn validate(&self) -> eyre::Result<()> {
+        if HeaderValue::from_str(&self.cache_control).is_err() {
+            bail!("`server.cache_control` is not a valid header value");
+        }
+
+        if self.body_limit == 0 {
+            bail!("`server.body_limit` must be greater than zero");
+        }
+
+        Ok(())
+    }
 }
 
 impl TryFromEnv for Config {
@@ -66,6 +97,8 @@ impl TryFromEnv for Config {
         Ok(Config {
             host: env::try_parse_or_else(HOST[0], env::try_parse_or_else(HOST[1], __default_host())?)?,
             port: env::try_parse_or_else(PORT[0], env::try_parse_or_else(PORT[1], __default_port())?)?,
+            cache_control: env::try_parse_or(CACHE_CONTROL, __default_cache_control)?,
+            body_limit: env::try_parse_or(BODY_LIMIT, __default_body_limit)?,
             ssl: match util::bool_env(ssl::ENABLED) {
                 Ok(true) => ssl::Config::try_from_env().map(Some)?,
                 Ok(false) => None,
@@ -83,3 +116,12 @@ fn __default_host() -> String {
 const fn __default_port() -> u16 {
     3621
 }
+
+#[inline]
+fn __default_cache_control() -> String {
+    String::from("public, max-age=31536000, immutable")
+}
+
+const fn __default_body_limit() -> usize {
+    15 * 1024 * 1024
+}
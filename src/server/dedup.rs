@@ -0,0 +1,60 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::metadata::{self, Image};
+use azalia::remi::{
+    StorageService,
+    core::{StorageService as _, UploadRequest},
+};
+
+/// Removes the data of `image` from the storage service once nothing refers to it anymore.
+///
+/// Aliases don't hold any data themselves, so nothing is removed for them. If `image` still
+/// has aliases, its data is handed over to the oldest alias, which the other aliases will
+/// then point to instead.
+pub async fn release(storage: &StorageService, metadata: &metadata::Store, image: &Image) -> eyre::Result<()> {
+    if image.alias_of.is_some() {
+        return Ok(());
+    }
+
+    let mut aliases = metadata.aliases(image).await?.into_iter();
+    if let Some(heir) = aliases.next() {
+        let Some(data) = storage.open(format!("./{}", image.name)).await? else {
+            bail!("image {} doesn't hold any data", image.name);
+        };
+
+        storage
+            .upload(
+                format!("./{}", heir.name),
+                UploadRequest::default()
+                    .with_content_type(Some(image.content_type.clone()))
+                    .with_data(data),
+            )
+            .await?;
+
+        let owner = heir.name.clone();
+        metadata.put(&Image { alias_of: None, ..heir }).await?;
+        for alias in aliases {
+            metadata
+                .put(&Image {
+                    alias_of: Some(owner.clone()),
+                    ..alias
+                })
+                .await?;
+        }
+    }
+
+    storage.delete(format!("./{}", image.name)).await.map_err(Into::into)
+}
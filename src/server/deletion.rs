@@ -0,0 +1,122 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::metadata::{self, Image};
+use azalia::remi::{
+    StorageService,
+    core::{StorageService as _, UploadRequest},
+};
+use rand::distr::{Alphanumeric, SampleString};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeSet,
+    sync::{Mutex, PoisonError},
+};
+use subtle::ConstantTimeEq;
+
+/// Prefix in the storage service where deletion tokens live. Only the SHA-256 digest
+/// of a token is kept, so access to the storage service doesn't hand out the ability to
+/// delete images.
+pub const PREFIX: &str = "./.ume/deletion-tokens";
+
+/// Names of the images that are being deleted by [`delete`], so that an image that is
+/// deleted from more than one place at once (like by the reaper and the `DELETE` route)
+/// only hands its data over to its aliases once.
+static DELETING: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Removes the image's name from [`DELETING`] once it's done being deleted, even if that
+/// failed.
+struct Deleting(String);
+
+impl Drop for Deleting {
+    fn drop(&mut self) {
+        DELETING.lock().unwrap_or_else(PoisonError::into_inner).remove(&self.0);
+    }
+}
+
+/// Generates a new deletion token.
+pub fn generate() -> String {
+    Alphanumeric.sample_string(&mut rand::rng(), 32)
+}
+
+/// Persists the digest of `token` for the image called `name`.
+pub async fn persist(storage: &StorageService, name: &str, token: &str) -> Result<(), azalia::remi::Error> {
+    storage
+        .upload(
+            format!("{PREFIX}/{name}"),
+            UploadRequest::default()
+                .with_content_type(Some("text/plain"))
+                .with_data(digest(token)),
+        )
+        .await
+}
+
+/// Checks whether if `token` is the deletion token that was minted for `name`.
+pub async fn verify(storage: &StorageService, name: &str, token: &str) -> Result<bool, azalia::remi::Error> {
+    let Some(expected) = storage.open(format!("{PREFIX}/{name}")).await? else {
+        return Ok(false);
+    };
+
+    Ok(bool::from(expected.as_ref().ct_eq(digest(token).as_bytes())))
+}
+
+/// Removes the deletion token of `name`, if one exists.
+pub async fn forget(storage: &StorageService, name: &str) -> Result<(), azalia::remi::Error> {
+    let path = format!("{PREFIX}/{name}");
+    if !storage.exists(&path).await? {
+        return Ok(());
+    }
+
+    storage.delete(path).await
+}
+
+/// Deletes `image` along with its record and deletion token. Its thumbnails are deleted
+/// once no other image shares them. Nothing is done if the image is already being deleted,
+/// or was deleted since `image` was read.
+pub async fn delete(
+    storage: &StorageService,
+    metadata: &metadata::Store,
+    thumbnails: &[u32],
+    image: &Image,
+) -> eyre::Result<()> {
+    if !DELETING
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(image.name.clone())
+    {
+        return Ok(());
+    }
+
+    let _deleting = Deleting(image.name.clone());
+    let Some(image) = metadata.get(&image.name).await? else {
+        return Ok(());
+    };
+
+    let image = &image;
+    super::dedup::release(storage, metadata, image).await?;
+    forget(storage, &image.name).await?;
+    metadata.delete(&image.name).await?;
+
+    // thumbnails are shared between every image with the same contents
+    if metadata.find_by_hash(&image.sha256, None).await?.is_none() {
+        super::thumbnail::forget(storage, &image.sha256, thumbnails).await?;
+    }
+
+    Ok(())
+}
+
+fn digest(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
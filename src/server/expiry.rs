@@ -0,0 +1,143 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::metadata::{self, Image};
+use crate::config::uploads;
+use azalia::remi::StorageService;
+use charted_core::serde::Duration;
+use chrono::{DateTime, TimeDelta, Utc};
+use std::str::FromStr;
+use tracing::Instrument;
+
+/// How often expired images are looked for.
+const INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Resolves when an image that is uploaded now expires from the expiry that the uploader
+/// asked for (like `24h`), or the configured default.
+pub fn resolve(config: &uploads::Config, requested: Option<&str>) -> Result<Option<DateTime<Utc>>, String> {
+    let requested = match requested.map(str::trim).filter(|requested| !requested.is_empty()) {
+        Some(requested) => Some(
+            Duration::from_str(requested)
+                .map(std::time::Duration::from)
+                .map_err(|_| format!("`{requested}` isn't a valid expiry"))?,
+        ),
+
+        None => None,
+    };
+
+    let max = config.max_expiry.map(std::time::Duration::from);
+    if let (Some(requested), Some(max)) = (requested, max)
+        && requested > max
+    {
+        return Err(format!("images can't live for longer than {}s", max.as_secs()));
+    }
+
+    if requested.is_some_and(|requested| requested.is_zero()) {
+        return Err(String::from("expiry must be longer than zero"));
+    }
+
+    let Some(expiry) = requested.or_else(|| config.default_expiry.map(Into::into)).or(max) else {
+        return Ok(None);
+    };
+
+    TimeDelta::from_std(expiry)
+        .ok()
+        .and_then(|expiry| Utc::now().checked_add_signed(expiry))
+        .map(Some)
+        .ok_or_else(|| String::from("expiry is too far in the future"))
+}
+
+/// Spawns a task that deletes expired images every [`INTERVAL`].
+pub fn spawn(storage: StorageService, metadata: metadata::Store, thumbnails: Vec<u32>) {
+    tokio::spawn(
+        async move {
+            let mut interval = tokio::time::interval(INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = reap(&storage, &metadata, &thumbnails).await {
+                    error!(error = %e, "unable to delete expired images");
+                    sentry::capture_error::<dyn std::error::Error>(e.as_ref());
+                }
+            }
+        }
+        .instrument(info_span!("ume.expiry.reaper")),
+    );
+}
+
+/// Deletes `image`, which has expired, in the background. An image that is requested over
+/// and over while it's being deleted is still only deleted once, see
+/// [`deletion::delete`][super::deletion::delete].
+pub fn delete(storage: StorageService, metadata: metadata::Store, thumbnails: Vec<u32>, image: Image) {
+    tokio::spawn(
+        async move {
+            if let Err(e) = super::deletion::delete(&storage, &metadata, &thumbnails, &image).await {
+                warn!(error = %e, file = %image.name, "unable to delete expired image");
+            }
+        }
+        .in_current_span(),
+    );
+}
+
+async fn reap(storage: &StorageService, metadata: &metadata::Store, thumbnails: &[u32]) -> eyre::Result<()> {
+    for image in metadata.expired(Utc::now()).await? {
+        info!(file = %image.name, "deleting expired image");
+
+        // one image that can't be deleted shouldn't keep the rest around
+        if let Err(e) = super::deletion::delete(storage, metadata, thumbnails, &image).await {
+            error!(error = %e, file = %image.name, "unable to delete expired image");
+            sentry::capture_error::<dyn std::error::Error>(e.as_ref());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::resolve;
+    use crate::config::uploads;
+    use charted_core::serde::Duration;
+    use chrono::{TimeDelta, Utc};
+
+    fn hours(hours: u64) -> Option<Duration> {
+        Some(Duration::from(std::time::Duration::from_secs(hours * 60 * 60)))
+    }
+
+    #[test]
+    fn resolves_expiry() {
+        let mut config = uploads::Config::default();
+        assert_eq!(resolve(&config, None), Ok(None));
+        assert_eq!(resolve(&config, Some("  ")), Ok(None));
+
+        let expires_at = resolve(&config, Some("1h")).unwrap().unwrap();
+        assert!((expires_at - Utc::now() - TimeDelta::hours(1)).abs() < TimeDelta::minutes(1));
+
+        assert!(resolve(&config, Some("soon")).is_err());
+        assert!(resolve(&config, Some("0s")).is_err());
+
+        // images expire after the default if they don't ask for anything else
+        config.default_expiry = hours(2);
+        let expires_at = resolve(&config, None).unwrap().unwrap();
+        assert!((expires_at - Utc::now() - TimeDelta::hours(2)).abs() < TimeDelta::minutes(1));
+
+        // and can't ask to live for longer than the maximum, which images live for otherwise
+        config.default_expiry = None;
+        config.max_expiry = hours(3);
+        assert!(resolve(&config, Some("4h")).is_err());
+
+        let expires_at = resolve(&config, None).unwrap().unwrap();
+        assert!((expires_at - Utc::now() - TimeDelta::hours(3)).abs() < TimeDelta::minutes(1));
+    }
+}
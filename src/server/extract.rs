@@ -15,7 +15,7 @@
 
 use axum::{
     extract::{FromRequest, Request},
-    http::header,
+    http::{header, StatusCode},
     response::IntoResponse,
     Json, RequestExt,
 };
@@ -141,11 +141,16 @@ pub fn err_to_msg(err: &multer::Error) -> &'static str {
         multer::Error::DecodeHeaderName { .. } => "decoding header name failed",
         multer::Error::DecodeHeaderValue { .. } => "decoding header value failed",
         multer::Error::FieldSizeExceeded { .. } => "exceeded field size capacity",
+        multer::Error::StreamSizeExceeded { .. } => "exceeded stream size capacity",
         multer::Error::StreamReadFailed(err) => {
             if let Some(err) = err.downcast_ref::<multer::Error>() {
                 return err_to_msg(err);
             }
 
+            if exceeded_body_limit(err.as_ref()) {
+                return "request body is larger than the server allows";
+            }
+
             "reading stream had failed"
         }
 
@@ -153,6 +158,21 @@ pub fn err_to_msg(err: &multer::Error) -> &'static str {
     }
 }
 
+/// Returns the status code that a request which failed with `err` is responded with.
+pub fn status_from_err(err: &multer::Error) -> StatusCode {
+    match err {
+        multer::Error::StreamReadFailed(err) if exceeded_body_limit(err.as_ref()) => StatusCode::PAYLOAD_TOO_LARGE,
+        _ => StatusCode::BAD_REQUEST,
+    }
+}
+
+/// Whether `err` happened because the request body was larger than [`DefaultBodyLimit`] allows.
+///
+/// [`DefaultBodyLimit`]: axum::extract::DefaultBodyLimit
+pub fn exceeded_body_limit(err: &(dyn std::error::Error + 'static)) -> bool {
+    std::iter::successors(Some(err), |err| err.source()).any(|err| err.is::<http_body_util::LengthLimitError>())
+}
+
 pub fn expand_details_from_err(err: &multer::Error) -> Option<Value> {
     match err {
         multer::Error::UnknownField { field_name } => field_name.as_ref().map(|field| json!({ "field": field })),
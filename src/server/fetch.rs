@@ -0,0 +1,456 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use axum::{body::Bytes, http::StatusCode};
+use futures_util::Stream;
+use reqwest::{
+    Response,
+    dns::{Addrs, Name, Resolve, Resolving},
+    header, redirect,
+};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+use url::{Host, Url};
+
+/// Downloads images from other servers for `POST /images/fetch`.
+///
+/// Unless `fetch.allow_private_addresses` is enabled, every address that is connected
+/// to (including the ones that redirects lead to) has to be reachable from the internet.
+/// Hostnames are checked after they're resolved, so a hostname can't resolve to a
+/// public address when it's checked and a private one when it's connected to.
+#[derive(Clone)]
+pub struct Fetcher {
+    client: reqwest::Client,
+    max_size: usize,
+    max_concurrent: usize,
+    allow_private_addresses: bool,
+
+    /// How many images each uploader is downloading right now.
+    downloads: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl Fetcher {
+    pub fn new(config: &crate::config::fetch::Config) -> eyre::Result<Self> {
+        let max_redirects = config.max_redirects;
+        let allow_private_addresses = config.allow_private_addresses;
+        let policy = redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > max_redirects {
+                return attempt.error(Refusal::Redirects(max_redirects));
+            }
+
+            match check(attempt.url(), allow_private_addresses) {
+                Ok(()) => attempt.follow(),
+                Err(refusal) => attempt.error(refusal),
+            }
+        });
+
+        // a proxy would resolve hostnames itself, which would skip the address checks
+        let mut builder = reqwest::Client::builder()
+            .user_agent(format!(
+                "auguwu/ume (+https://github.com/auguwu/ume; {})",
+                crate::version()
+            ))
+            .timeout(config.timeout.into())
+            .redirect(policy)
+            .no_proxy();
+
+        if !allow_private_addresses {
+            builder = builder.dns_resolver(Arc::new(PublicResolver));
+        }
+
+        Ok(Self {
+            client: builder.build()?,
+            max_size: config.max_size,
+            max_concurrent: config.max_concurrent,
+            allow_private_addresses,
+            downloads: Arc::default(),
+        })
+    }
+
+    /// Counts a download by `uploader` until the returned [`Permit`] is dropped, unless they
+    /// are already downloading as many images as `fetch.max_concurrent` allows.
+    pub fn permit(&self, uploader: &str) -> Result<Permit, Error> {
+        let mut downloads = lock(&self.downloads);
+        let count = downloads.entry(uploader.to_owned()).or_default();
+        if *count >= self.max_concurrent {
+            return Err(Error::Busy(self.max_concurrent));
+        }
+
+        *count += 1;
+        Ok(Permit {
+            downloads: Arc::clone(&self.downloads),
+            uploader: uploader.to_owned(),
+        })
+    }
+
+    /// Starts downloading the image at `url`. Only the response's headers have been
+    /// received when this returns, the image itself is received with [`Download::chunks`].
+    pub async fn fetch(&self, url: Url) -> Result<Download, Error> {
+        check(&url, self.allow_private_addresses).map_err(Error::Refused)?;
+
+        let response = self.client.get(url).header(header::ACCEPT, "image/*").send().await?;
+
+        if !response.status().is_success() {
+            return Err(Error::Status(response.status()));
+        }
+
+        // the contents are still sniffed once they're received, this only turns away
+        // anything that the server itself says isn't an image
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+
+        if !content_type
+            .get(.."image/".len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case("image/"))
+        {
+            return Err(Error::ContentType(content_type.to_owned()));
+        }
+
+        if response
+            .content_length()
+            .is_some_and(|length| length > self.max_size as u64)
+        {
+            return Err(Error::TooLarge(self.max_size));
+        }
+
+        Ok(Download {
+            response,
+            max_size: self.max_size,
+        })
+    }
+}
+
+/// Download of an uploader that counts towards `fetch.max_concurrent`, see [`Fetcher::permit`].
+pub struct Permit {
+    downloads: Arc<Mutex<HashMap<String, usize>>>,
+    uploader: String,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut downloads = lock(&self.downloads);
+        if let Some(count) = downloads.get_mut(&self.uploader) {
+            *count -= 1;
+            if *count == 0 {
+                downloads.remove(&self.uploader);
+            }
+        }
+    }
+}
+
+fn lock(downloads: &Mutex<HashMap<String, usize>>) -> MutexGuard<'_, HashMap<String, usize>> {
+    downloads.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Image that is being downloaded.
+pub struct Download {
+    response: Response,
+    max_size: usize,
+}
+
+impl Download {
+    /// Last segment of the URL that the image was downloaded from, after redirects.
+    pub fn filename(&self) -> Option<String> {
+        self.response
+            .url()
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .filter(|segment| !segment.is_empty())
+            .map(ToOwned::to_owned)
+    }
+
+    /// Receives the image, which fails once it's larger than `fetch.max_size`.
+    pub fn chunks(self) -> impl Stream<Item = Result<Bytes, Error>> {
+        let max_size = self.max_size;
+        futures_util::stream::try_unfold((self.response, 0usize), move |(mut response, received)| async move {
+            let Some(chunk) = response.chunk().await? else {
+                return Ok(None);
+            };
+
+            let received = received.saturating_add(chunk.len());
+            if received > max_size {
+                return Err(Error::TooLarge(max_size));
+            }
+
+            Ok(Some((chunk, (response, received))))
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The URL was refused before anything was downloaded from it.
+    Refused(Refusal),
+
+    /// The image is larger than `fetch.max_size`, which is kept here.
+    TooLarge(usize),
+
+    /// The uploader is already downloading as many images as `fetch.max_concurrent`, which
+    /// is kept here, allows.
+    Busy(usize),
+
+    /// The server responded with a status code that wasn't successful.
+    Status(reqwest::StatusCode),
+
+    /// The server responded with something other than an image.
+    ContentType(String),
+
+    /// Downloading the image took longer than `fetch.timeout`.
+    Timeout,
+
+    /// Error that occurred while connecting to the server or receiving the image.
+    Request(reqwest::Error),
+}
+
+impl Error {
+    /// Returns the status code that the uploader is responded with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::Refused(Refusal::Redirects(_)) => StatusCode::BAD_GATEWAY,
+            Error::Refused(_) => StatusCode::BAD_REQUEST,
+            Error::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::Busy(_) => StatusCode::TOO_MANY_REQUESTS,
+            Error::ContentType(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            Error::Status(_) | Error::Request(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Refused(refusal) => Display::fmt(refusal, f),
+            Error::TooLarge(max) => write!(f, "image is larger than the server allows ({max} bytes)"),
+            Error::Busy(max) => write!(f, "only {max} images can be downloaded at once, pls try again later"),
+            Error::Status(status) => write!(f, "server responded with {status}"),
+            Error::ContentType(ct) if ct.is_empty() => f.write_str("server didn't respond with an image"),
+            Error::ContentType(ct) => write!(f, "server responded with `{ct}`, not an image"),
+            Error::Timeout => f.write_str("server took too long to respond with the image"),
+            Error::Request(_) => f.write_str("unable to download the image from the server"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Refused(refusal) => Some(refusal),
+            Error::Request(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(value: reqwest::Error) -> Self {
+        if value.is_timeout() {
+            return Self::Timeout;
+        }
+
+        // refusals from resolving hostnames and following redirects end up somewhere
+        // in the error's sources
+        let refusal = std::iter::successors(Some(&value as &(dyn std::error::Error + 'static)), |err| err.source())
+            .find_map(|err| err.downcast_ref::<Refusal>());
+
+        match refusal {
+            Some(refusal) => Self::Refused(refusal.clone()),
+            None => Self::Request(value),
+        }
+    }
+}
+
+/// Why a URL was refused.
+#[derive(Debug, Clone)]
+pub enum Refusal {
+    /// Only `http` and `https` URLs can be downloaded from.
+    Scheme(String),
+
+    /// The host isn't reachable from the internet.
+    Address(String),
+
+    /// More redirects than `fetch.max_redirects` (which is kept here) were followed.
+    Redirects(usize),
+}
+
+impl Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Refusal::Scheme(scheme) => write!(f, "can't download images from `{scheme}` urls"),
+            Refusal::Address(host) => write!(f, "`{host}` isn't a public address"),
+            Refusal::Redirects(max) => write!(f, "server redirected more than {max} times"),
+        }
+    }
+}
+
+impl std::error::Error for Refusal {}
+
+/// Checks the parts of `url` that can be checked before connecting to it. Hostnames
+/// are checked by [`PublicResolver`] once they're resolved.
+fn check(url: &Url, allow_private_addresses: bool) -> Result<(), Refusal> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(Refusal::Scheme(url.scheme().to_owned()));
+    }
+
+    let ip = match url.host() {
+        Some(Host::Ipv4(ip)) => IpAddr::V4(ip),
+        Some(Host::Ipv6(ip)) => IpAddr::V6(ip),
+        _ => return Ok(()),
+    };
+
+    match allow_private_addresses || is_public(ip) {
+        true => Ok(()),
+        false => Err(Refusal::Address(ip.to_string())),
+    }
+}
+
+/// Resolves hostnames to the addresses that are reachable from the internet, and
+/// refuses hostnames that don't resolve to any of them.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs = tokio::net::lookup_host((host, 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect::<Vec<SocketAddr>>();
+
+            if addrs.is_empty() {
+                return Err(Refusal::Address(host.to_owned()).into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Whether `ip` is reachable from the internet, which is what `IpAddr::is_global` checks
+/// for once it's stable.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0 // "this network"
+                || (a == 100 && (b & 0xc0) == 64) // shared address space, 100.64.0.0/10
+                || (a == 192 && b == 0 && c == 0) // protocol assignments, 192.0.0.0/24
+                || (a == 198 && (b & 0xfe) == 18) // benchmarking, 198.18.0.0/15
+                || a >= 240) // reserved
+        }
+
+        IpAddr::V6(ip) => {
+            // addresses that embed an IPv4 address are as reachable as that address is
+            let segments = ip.segments();
+            let embedded = match segments {
+                [0, 0, 0, 0, 0, 0xffff, ..] | [0x64, 0xff9b, 0, 0, 0, 0, ..] => {
+                    Some(Ipv4Addr::from_bits(u128::from(ip) as u32))
+                }
+
+                [0x2002, high, low, ..] => Some(Ipv4Addr::from_bits((u32::from(high) << 16) | u32::from(low))),
+                _ => None,
+            };
+
+            if let Some(embedded) = embedded {
+                return is_public(IpAddr::V4(embedded));
+            }
+
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()
+                || (segments[0] & 0xffc0) == 0xfec0 // site-local, fec0::/10
+                || matches!(segments, [0, 0, 0, 0, 0, 0, ..]) // IPv4-compatible, ::/96
+                || matches!(segments, [0x64, 0xff9b, 1, ..]) // local-use NAT64, 64:ff9b:1::/48
+                || (segments[0] == 0x2001 && segments[1] == 0) // Teredo, 2001::/32
+                || (segments[0] == 0x2001 && segments[1] == 0xdb8)) // documentation, 2001:db8::/32
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    #[test]
+    fn is_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "2002:c0a8:101::1",
+            "::a9fe:a9fe",
+            "64:ff9b:1::a9fe:a9fe",
+            "2001:0:4136:e378:8000:63bf:3fff:fdd2",
+        ] {
+            assert!(
+                !super::is_public(ip.parse::<IpAddr>().unwrap()),
+                "{ip} should be refused"
+            );
+        }
+
+        for ip in ["1.1.1.1", "93.184.216.34", "2606:4700:4700::1111", "::ffff:1.1.1.1"] {
+            assert!(
+                super::is_public(ip.parse::<IpAddr>().unwrap()),
+                "{ip} should be allowed"
+            );
+        }
+    }
+
+    #[test]
+    fn permits() {
+        let config = crate::config::fetch::Config {
+            max_concurrent: 2,
+            ..Default::default()
+        };
+
+        let fetcher = super::Fetcher::new(&config).unwrap();
+        let first = fetcher.permit("noel").unwrap();
+        let _second = fetcher.permit("noel").unwrap();
+        assert!(matches!(fetcher.permit("noel"), Err(super::Error::Busy(2))));
+
+        // every uploader has their own limit
+        let _other = fetcher.permit("someone").unwrap();
+
+        drop(first);
+        assert!(fetcher.permit("noel").is_ok());
+    }
+}
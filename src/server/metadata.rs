@@ -0,0 +1,284 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod sidecar;
+mod sqlite;
+
+use crate::config::metadata::Config;
+use azalia::remi::StorageService;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Metadata that is recorded for every uploaded image.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Image {
+    /// Name of the image in the storage service.
+    pub name: String,
+
+    /// Name of the uploader account that uploaded this image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uploader: Option<String>,
+
+    /// Filename that the image had on the uploader's machine, if one was sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_filename: Option<String>,
+
+    /// When the image was uploaded.
+    pub uploaded_at: DateTime<Utc>,
+
+    /// Size of the image in bytes.
+    pub size: u64,
+
+    /// Width of the image in pixels, if it could be decoded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+
+    /// Height of the image in pixels, if it could be decoded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+
+    /// Hex-encoded SHA-256 digest of the image's contents.
+    pub sha256: String,
+
+    /// Content type of the image.
+    pub content_type: String,
+
+    /// Name of the image whose data this image shares, if this image was deduplicated
+    /// into an alias.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias_of: Option<String>,
+
+    /// When the image expires, after which it is no longer served and gets deleted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+
+    /// How many times the image can be viewed, after which it is no longer served and
+    /// gets deleted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_views: Option<u64>,
+
+    /// How many times the image was viewed. Views are only counted for images with
+    /// [`max_views`](Image::max_views) set.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub views: u64,
+
+    /// How many times bots fetched the image to show a preview of it, which are counted
+    /// separately from [`views`](Image::views).
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub preview_views: u64,
+
+    /// Whether the image can only be viewed with a signed URL.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub private: bool,
+
+    /// Argon2 hash of the password that has to be given to view the image, in the PHC
+    /// string format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+impl Image {
+    /// Name of the blob in the storage service that holds this image's data.
+    pub fn blob(&self) -> &str {
+        self.alias_of.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Whether the image expires or can only be viewed so many times.
+    pub fn ephemeral(&self) -> bool {
+        self.expires_at.is_some() || self.max_views.is_some()
+    }
+
+    /// Whether the image can only be viewed with a password.
+    pub fn protected(&self) -> bool {
+        self.password.is_some()
+    }
+
+    /// Whether the image has expired or ran out of views.
+    pub fn expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= Utc::now())
+            || self.max_views.is_some_and(|max_views| self.views >= max_views)
+    }
+}
+
+/// Images that were uploaded together, which are shown in order on the album's page.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Album {
+    /// Short ID of the album, which is part of its URL.
+    pub id: String,
+
+    /// Title of the album, if one was given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    /// Name of the uploader account that created this album.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uploader: Option<String>,
+
+    /// When the album was created.
+    pub created_at: DateTime<Utc>,
+
+    /// Images in the album, in the order that they were uploaded in.
+    pub images: Vec<AlbumImage>,
+}
+
+/// An image in an [`Album`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlbumImage {
+    /// Name of the image.
+    pub name: String,
+
+    /// Caption that is shown below the image, if one was given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+}
+
+fn is_zero(views: &u64) -> bool {
+    *views == 0
+}
+
+/// Store that keeps an [`Image`] record for every upload.
+#[derive(Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum Store {
+    Sqlite(sqlite::Store),
+    Sidecar(sidecar::Store),
+}
+
+impl Store {
+    /// Creates the store that was configured in `config`.
+    pub fn new(config: &Config, storage: &StorageService) -> eyre::Result<Store> {
+        match config {
+            Config::Sqlite(sqlite) => {
+                let path = sqlite
+                    .path
+                    .as_deref()
+                    .ok_or_else(|| eyre!("path to the metadata database was never resolved"))?;
+
+                sqlite::Store::open(path).map(Store::Sqlite)
+            }
+
+            Config::Sidecar => Ok(Store::Sidecar(sidecar::Store::new(storage.clone()))),
+        }
+    }
+
+    /// Returns the record of the image called `name`, if one exists.
+    pub async fn get(&self, name: &str) -> eyre::Result<Option<Image>> {
+        match self {
+            Store::Sqlite(store) => store.get(name).await,
+            Store::Sidecar(store) => store.get(name).await,
+        }
+    }
+
+    /// Inserts `image`, or replaces the record if one already exists with the same name.
+    pub async fn put(&self, image: &Image) -> eyre::Result<()> {
+        match self {
+            Store::Sqlite(store) => store.put(image).await,
+            Store::Sidecar(store) => store.put(image).await,
+        }
+    }
+
+    /// Inserts `image` unless a record already exists with the same name, and returns whether
+    /// it was inserted. This is atomic, so only one of two uploads that want the same name
+    /// can get it.
+    pub async fn reserve(&self, image: &Image) -> eyre::Result<bool> {
+        match self {
+            Store::Sqlite(store) => store.reserve(image).await,
+            Store::Sidecar(store) => store.reserve(image).await,
+        }
+    }
+
+    /// Finds an image that holds its own data (so, not an alias) with the SHA-256 digest `sha256`.
+    /// If `uploader` is given, then only images that were uploaded by them are considered.
+    /// Images that can be shared (the ones that don't expire and can be seen by everyone)
+    /// are preferred over the ones that can't.
+    pub async fn find_by_hash(&self, sha256: &str, uploader: Option<&str>) -> eyre::Result<Option<Image>> {
+        match self {
+            Store::Sqlite(store) => store.find_by_hash(sha256, uploader).await,
+            Store::Sidecar(store) => store.find_by_hash(sha256, uploader).await,
+        }
+    }
+
+    /// Returns every image that is an alias of `image`.
+    pub async fn aliases(&self, image: &Image) -> eyre::Result<Vec<Image>> {
+        match self {
+            Store::Sqlite(store) => store.aliases(image).await,
+            Store::Sidecar(store) => store.aliases(image).await,
+        }
+    }
+
+    /// Counts a view of the image called `name`, which has to have a view limit. Returns
+    /// how many views it has left, or `None` if it had already ran out of views. Views
+    /// are counted atomically, so concurrent requests can't view it more times than
+    /// it allows.
+    pub async fn view(&self, name: &str) -> eyre::Result<Option<u64>> {
+        match self {
+            Store::Sqlite(store) => store.view(name).await,
+            Store::Sidecar(store) => store.view(name).await,
+        }
+    }
+
+    /// Counts a preview of the image called `name` by a bot, which doesn't use up one of its
+    /// views. Returns how many previews it has left out of `allowance`, or `None` if it had
+    /// already ran out of them. Previews are counted atomically as well.
+    pub async fn preview(&self, name: &str, allowance: u64) -> eyre::Result<Option<u64>> {
+        match self {
+            Store::Sqlite(store) => store.preview(name, allowance).await,
+            Store::Sidecar(store) => store.preview(name, allowance).await,
+        }
+    }
+
+    /// Returns every image that expired at or before `now`, or ran out of views.
+    pub async fn expired(&self, now: DateTime<Utc>) -> eyre::Result<Vec<Image>> {
+        match self {
+            Store::Sqlite(store) => store.expired(now).await,
+            Store::Sidecar(store) => store.expired(now).await,
+        }
+    }
+
+    /// Returns up to `limit` images, sorted by when they were uploaded and then by their
+    /// name, starting after the image that was uploaded at `after` (in milliseconds) and
+    /// called the given name.
+    pub async fn list(&self, after: Option<(i64, String)>, descending: bool, limit: usize) -> eyre::Result<Vec<Image>> {
+        match self {
+            Store::Sqlite(store) => store.list(after, descending, limit).await,
+            Store::Sidecar(store) => store.list(after, descending, limit).await,
+        }
+    }
+
+    /// Returns the album with the ID `id`, if one exists.
+    pub async fn get_album(&self, id: &str) -> eyre::Result<Option<Album>> {
+        match self {
+            Store::Sqlite(store) => store.get_album(id).await,
+            Store::Sidecar(store) => store.get_album(id).await,
+        }
+    }
+
+    /// Inserts `album` unless an album already exists with the same ID, and returns whether
+    /// it was inserted. This is atomic, so two albums never get the same ID.
+    pub async fn reserve_album(&self, album: &Album) -> eyre::Result<bool> {
+        match self {
+            Store::Sqlite(store) => store.reserve_album(album).await,
+            Store::Sidecar(store) => store.reserve_album(album).await,
+        }
+    }
+
+    /// Removes the record of the image called `name`, if one exists.
+    pub async fn delete(&self, name: &str) -> eyre::Result<()> {
+        match self {
+            Store::Sqlite(store) => store.delete(name).await,
+            Store::Sidecar(store) => store.delete(name).await,
+        }
+    }
+}
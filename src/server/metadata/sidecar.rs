@@ -0,0 +1,484 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Album, Image};
+use chrono::{DateTime, Utc};
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::sync::RwLock;
+use azalia::remi::{
+    StorageService,
+    core::{Blob, ListBlobsRequest, StorageService as _, UploadRequest},
+};
+
+/// Prefix in the storage service where the JSON objects live.
+pub const PREFIX: &str = "./.ume/metadata";
+
+/// Path of the object that keeps the names of every image that expires or has a view limit.
+const EPHEMERAL: &str = "./.ume/metadata/index/ephemeral.json";
+
+/// Path of the object that keeps every day (as `YYYY-MM-DD`) that images were uploaded on.
+const DAYS: &str = "./.ume/metadata/index/days.json";
+
+/// Keeps metadata as `{name}.json` objects in the storage service. Since the storage service
+/// can't be queried, the names of every image with the same SHA-256 digest are also kept
+/// in a `sha256/{digest}.json` object, and the names of every image that expires or has a
+/// view limit are kept in `index/ephemeral.json`. Images are listed from the names of every
+/// image that was uploaded on the same day, which are kept in `index/uploaded/{day}.json`,
+/// along with the days themselves in `index/days.json`. Albums are kept as `albums/{id}.json`.
+///
+/// The storage service can't update objects atomically either (objects are overwritten by
+/// deleting them first), so views are counted, names are reserved and the indexes are
+/// updated while holding a lock, which records are only read while holding too. This means
+/// that they're only updated atomically within one **ume** server.
+#[derive(Clone)]
+pub struct Store(StorageService, Arc<RwLock<()>>);
+
+impl Store {
+    pub fn new(storage: StorageService) -> Store {
+        Store(storage, Arc::default())
+    }
+
+    pub async fn get(&self, name: &str) -> eyre::Result<Option<Image>> {
+        let _guard = self.1.read().await;
+        self.read(name).await
+    }
+
+    /// Reads the record of the image called `name`. This has to be called while holding the lock.
+    async fn read(&self, name: &str) -> eyre::Result<Option<Image>> {
+        let Some(data) = self.0.open(path(name)).await? else {
+            return Ok(None);
+        };
+
+        serde_json::from_slice(&data).map(Some).map_err(Into::into)
+    }
+
+    pub async fn put(&self, image: &Image) -> eyre::Result<()> {
+        let _guard = self.1.write().await;
+        self.insert(image).await
+    }
+
+    pub async fn reserve(&self, image: &Image) -> eyre::Result<bool> {
+        let _guard = self.1.write().await;
+        if self.0.exists(path(&image.name)).await? {
+            return Ok(false);
+        }
+
+        self.insert(image).await.map(|_| true)
+    }
+
+    /// Writes `image` and adds it to the indexes. This has to be called while holding the lock.
+    async fn insert(&self, image: &Image) -> eyre::Result<()> {
+        let mut names = self.names(&image.sha256).await?;
+        if !names.contains(&image.name) {
+            names.push(image.name.clone());
+            self.write(&index_path(&image.sha256), serde_json::to_vec(&names)?)
+                .await?;
+        }
+
+        let mut ephemeral = self.ephemeral().await?;
+        if image.ephemeral() != ephemeral.contains(&image.name) {
+            ephemeral.retain(|name| name != &image.name);
+            if image.ephemeral() {
+                ephemeral.push(image.name.clone());
+            }
+
+            self.write(EPHEMERAL, serde_json::to_vec(&ephemeral)?).await?;
+        }
+
+        let day = day(&image.uploaded_at);
+        let entry = (image.uploaded_at.timestamp_millis(), image.name.clone());
+
+        // the days have to be built (if they weren't yet) before this image is kept
+        let mut days = self.days().await?;
+        let mut uploads = self.uploads(&day).await?;
+        if !uploads.contains(&entry) {
+            uploads.push(entry);
+            self.write(&uploads_path(&day), serde_json::to_vec(&uploads)?).await?;
+
+            if !days.contains(&day) {
+                days.push(day);
+                days.sort();
+                self.write(DAYS, serde_json::to_vec(&days)?).await?;
+            }
+        }
+
+        self.write(&path(&image.name), serde_json::to_vec(image)?).await
+    }
+
+    pub async fn find_by_hash(&self, sha256: &str, uploader: Option<&str>) -> eyre::Result<Option<Image>> {
+        let _guard = self.1.read().await;
+        let mut found = None;
+        for name in self.names(sha256).await? {
+            if let Some(image) = self.read(&name).await?
+                && image.alias_of.is_none()
+                && uploader.is_none_or(|uploader| image.uploader.as_deref() == Some(uploader))
+            {
+                if !image.ephemeral() && !image.private && !image.protected() {
+                    return Ok(Some(image));
+                }
+
+                found.get_or_insert(image);
+            }
+        }
+
+        Ok(found)
+    }
+
+    pub async fn aliases(&self, image: &Image) -> eyre::Result<Vec<Image>> {
+        let _guard = self.1.read().await;
+        let mut aliases = Vec::new();
+        for name in self.names(&image.sha256).await? {
+            if let Some(alias) = self.read(&name).await?
+                && alias.alias_of.as_ref() == Some(&image.name)
+            {
+                aliases.push(alias);
+            }
+        }
+
+        Ok(aliases)
+    }
+
+    pub async fn view(&self, name: &str) -> eyre::Result<Option<u64>> {
+        let _guard = self.1.write().await;
+        let Some(mut image) = self.read(name).await? else {
+            return Ok(None);
+        };
+
+        let Some(max_views) = image.max_views.filter(|max_views| image.views < *max_views) else {
+            return Ok(None);
+        };
+
+        image.views += 1;
+        self.write(&path(name), serde_json::to_vec(&image)?).await?;
+
+        Ok(Some(max_views - image.views))
+    }
+
+    pub async fn preview(&self, name: &str, allowance: u64) -> eyre::Result<Option<u64>> {
+        let _guard = self.1.write().await;
+        let Some(mut image) = self.read(name).await? else {
+            return Ok(None);
+        };
+
+        if image.preview_views >= allowance {
+            return Ok(None);
+        }
+
+        image.preview_views += 1;
+        self.write(&path(name), serde_json::to_vec(&image)?).await?;
+
+        Ok(Some(allowance - image.preview_views))
+    }
+
+    pub async fn expired(&self, now: DateTime<Utc>) -> eyre::Result<Vec<Image>> {
+        let _guard = self.1.read().await;
+        let mut expired = Vec::new();
+        for name in self.ephemeral().await? {
+            if let Some(image) = self.read(&name).await?
+                && (image.expires_at.is_some_and(|expires_at| expires_at <= now)
+                    || image.max_views.is_some_and(|max_views| image.views >= max_views))
+            {
+                expired.push(image);
+            }
+        }
+
+        expired.sort_by_key(|image| image.expires_at);
+        Ok(expired)
+    }
+
+    pub async fn delete(&self, name: &str) -> eyre::Result<()> {
+        let _guard = self.1.write().await;
+        let Some(image) = self.read(name).await? else {
+            return Ok(());
+        };
+
+        let mut names = self.names(&image.sha256).await?;
+        names.retain(|n| n != name);
+
+        let index = index_path(&image.sha256);
+        if names.is_empty() {
+            self.remove(&index).await?;
+        } else {
+            self.write(&index, serde_json::to_vec(&names)?).await?;
+        }
+
+        let mut ephemeral = self.ephemeral().await?;
+        if ephemeral.contains(&image.name) {
+            ephemeral.retain(|n| n != name);
+            self.write(EPHEMERAL, serde_json::to_vec(&ephemeral)?).await?;
+        }
+
+        let day = day(&image.uploaded_at);
+        let mut uploads = self.uploads(&day).await?;
+        uploads.retain(|(_, n)| n != name);
+
+        if uploads.is_empty() {
+            self.remove(&uploads_path(&day)).await?;
+
+            let mut days = self.days().await?;
+            days.retain(|d| d != &day);
+            self.write(DAYS, serde_json::to_vec(&days)?).await?;
+        } else {
+            self.write(&uploads_path(&day), serde_json::to_vec(&uploads)?).await?;
+        }
+
+        self.remove(&path(name)).await
+    }
+
+    pub async fn list(&self, after: Option<(i64, String)>, descending: bool, limit: usize) -> eyre::Result<Vec<Image>> {
+        let mut days = {
+            let _guard = self.1.write().await;
+            self.days().await?
+        };
+
+        let _guard = self.1.read().await;
+
+        if descending {
+            days.reverse();
+        }
+
+        // days before the one that the cursor is in were already listed
+        let first = after
+            .as_ref()
+            .and_then(|(uploaded_at, _)| DateTime::from_timestamp_millis(*uploaded_at))
+            .map(|uploaded_at| day(&uploaded_at));
+
+        let mut images = Vec::new();
+        for day in days {
+            if let Some(ref first) = first
+                && ((descending && day > *first) || (!descending && day < *first))
+            {
+                continue;
+            }
+
+            let mut uploads = self.uploads(&day).await?;
+            uploads.sort();
+            if descending {
+                uploads.reverse();
+            }
+
+            for entry in uploads {
+                if let Some(ref after) = after
+                    && ((descending && entry >= *after) || (!descending && entry <= *after))
+                {
+                    continue;
+                }
+
+                if let Some(image) = self.read(&entry.1).await? {
+                    images.push(image);
+                    if images.len() >= limit {
+                        return Ok(images);
+                    }
+                }
+            }
+        }
+
+        Ok(images)
+    }
+
+    pub async fn get_album(&self, id: &str) -> eyre::Result<Option<Album>> {
+        let _guard = self.1.read().await;
+        let Some(data) = self.0.open(album_path(id)).await? else {
+            return Ok(None);
+        };
+
+        serde_json::from_slice(&data).map(Some).map_err(Into::into)
+    }
+
+    pub async fn reserve_album(&self, album: &Album) -> eyre::Result<bool> {
+        let _guard = self.1.write().await;
+        if self.0.exists(album_path(&album.id)).await? {
+            return Ok(false);
+        }
+
+        self.write(&album_path(&album.id), serde_json::to_vec(album)?)
+            .await
+            .map(|_| true)
+    }
+
+    /// Returns the names of every image with the SHA-256 digest `sha256`.
+    async fn names(&self, sha256: &str) -> eyre::Result<Vec<String>> {
+        match self.0.open(index_path(sha256)).await? {
+            Some(data) => serde_json::from_slice(&data).map_err(Into::into),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the names of every image that expires or has a view limit.
+    async fn ephemeral(&self) -> eyre::Result<Vec<String>> {
+        match self.0.open(EPHEMERAL).await? {
+            Some(data) => serde_json::from_slice(&data).map_err(Into::into),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the names of every image that was uploaded on `day`, along with when they
+    /// were uploaded (in milliseconds).
+    async fn uploads(&self, day: &str) -> eyre::Result<Vec<(i64, String)>> {
+        match self.0.open(uploads_path(day)).await? {
+            Some(data) => serde_json::from_slice(&data).map_err(Into::into),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns every day that images were uploaded on, in order. If they were never kept,
+    /// then they're built from every record, which has to be done while holding the lock.
+    async fn days(&self) -> eyre::Result<Vec<String>> {
+        if let Some(data) = self.0.open(DAYS).await? {
+            return serde_json::from_slice(&data).map_err(Into::into);
+        }
+
+        // images that were uploaded before the days were kept have to be listed too
+        let blobs = self
+            .0
+            .blobs(
+                Some(PREFIX),
+                Some(ListBlobsRequest {
+                    include_dirs: true,
+                    ..Default::default()
+                }),
+            )
+            .await?;
+
+        let mut uploads = BTreeMap::<String, Vec<(i64, String)>>::new();
+        for blob in blobs {
+            let Blob::File(file) = blob else {
+                continue;
+            };
+
+            // the indexes and albums are in the same prefix, but aren't records of an image
+            let Some(name) = file.name.rsplit('/').next().and_then(|name| name.strip_suffix(".json")) else {
+                continue;
+            };
+
+            if let Some(image) = self.read(name).await?
+                && image.name == name
+            {
+                uploads
+                    .entry(day(&image.uploaded_at))
+                    .or_default()
+                    .push((image.uploaded_at.timestamp_millis(), image.name));
+            }
+        }
+
+        for (day, uploads) in &uploads {
+            self.write(&uploads_path(day), serde_json::to_vec(uploads)?).await?;
+        }
+
+        let days = uploads.into_keys().collect::<Vec<_>>();
+        self.write(DAYS, serde_json::to_vec(&days)?).await?;
+
+        Ok(days)
+    }
+
+    async fn write(&self, path: &str, data: Vec<u8>) -> eyre::Result<()> {
+        // the filesystem storage service doesn't truncate files that already exist
+        self.remove(path).await?;
+        self.0
+            .upload(
+                path,
+                UploadRequest::default()
+                    .with_content_type(Some("application/json"))
+                    .with_data(data),
+            )
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn remove(&self, path: &str) -> eyre::Result<()> {
+        if !self.0.exists(path).await? {
+            return Ok(());
+        }
+
+        self.0.delete(path).await.map_err(Into::into)
+    }
+}
+
+fn path(name: &str) -> String {
+    format!("{PREFIX}/{name}.json")
+}
+
+fn index_path(sha256: &str) -> String {
+    format!("{PREFIX}/sha256/{sha256}.json")
+}
+
+fn uploads_path(day: &str) -> String {
+    format!("{PREFIX}/index/uploaded/{day}.json")
+}
+
+fn day(uploaded_at: &DateTime<Utc>) -> String {
+    uploaded_at.format("%Y-%m-%d").to_string()
+}
+
+fn album_path(id: &str) -> String {
+    format!("{PREFIX}/albums/{id}.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Image, Store};
+    use azalia::remi::{StorageService, core::StorageService as _, fs};
+    use chrono::Utc;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn records_are_never_missing_while_viewed() {
+        let dir = TempDir::new().unwrap();
+        let storage = StorageService::Filesystem(fs::StorageService::new(dir.path()));
+        storage.init().await.unwrap();
+
+        let store = Store::new(storage);
+        store
+            .put(&Image {
+                name: String::from("abcdef.png"),
+                uploader: None,
+                original_filename: None,
+                uploaded_at: Utc::now(),
+                size: 1024,
+                width: None,
+                height: None,
+                sha256: String::from("00"),
+                content_type: String::from("image/png"),
+                alias_of: None,
+                expires_at: None,
+                max_views: Some(100),
+                views: 0,
+                preview_views: 0,
+                private: true,
+                password: None,
+            })
+            .await
+            .unwrap();
+
+        // records are overwritten by deleting them first, which readers can't ever see
+        let viewer = tokio::spawn({
+            let store = store.clone();
+            async move {
+                for _ in 0..100 {
+                    store.view("abcdef.png").await.unwrap().unwrap();
+                }
+            }
+        });
+
+        while !viewer.is_finished() {
+            let image = store.get("abcdef.png").await.unwrap();
+            assert!(image.is_some_and(|image| image.private));
+            tokio::task::yield_now().await;
+        }
+
+        viewer.await.unwrap();
+        assert_eq!(store.get("abcdef.png").await.unwrap().unwrap().views, 100);
+    }
+}
@@ -0,0 +1,523 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Album, AlbumImage, Image};
+use chrono::{DateTime, Utc};
+use eyre::Context;
+use rusqlite::{Connection, ErrorCode, OptionalExtension, Row, params};
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+/// Migrations that are applied in order. The index of the last applied migration is
+/// tracked in SQLite's `user_version` pragma.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE images (
+        name              TEXT PRIMARY KEY NOT NULL,
+        uploader          TEXT,
+        original_filename TEXT,
+        uploaded_at       INTEGER NOT NULL,
+        size              INTEGER NOT NULL,
+        width             INTEGER,
+        height            INTEGER,
+        sha256            TEXT NOT NULL,
+        content_type      TEXT NOT NULL
+    );",
+    "ALTER TABLE images ADD COLUMN alias_of TEXT;
+     CREATE INDEX images_sha256 ON images (sha256);
+     CREATE INDEX images_alias_of ON images (alias_of);",
+    "ALTER TABLE images ADD COLUMN expires_at INTEGER;
+     CREATE INDEX images_expires_at ON images (expires_at);",
+    "ALTER TABLE images ADD COLUMN max_views INTEGER;
+     ALTER TABLE images ADD COLUMN views INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE images ADD COLUMN private INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE images ADD COLUMN password TEXT;",
+    "CREATE TABLE albums (
+        id         TEXT PRIMARY KEY NOT NULL,
+        title      TEXT,
+        uploader   TEXT,
+        created_at INTEGER NOT NULL
+    );
+    CREATE TABLE album_images (
+        album    TEXT NOT NULL REFERENCES albums (id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        name     TEXT NOT NULL,
+        caption  TEXT,
+        PRIMARY KEY (album, position)
+    );",
+    "CREATE INDEX images_uploaded_at ON images (uploaded_at, name);",
+    "ALTER TABLE images ADD COLUMN preview_views INTEGER NOT NULL DEFAULT 0;",
+];
+
+/// Keeps metadata in an embedded SQLite database.
+#[derive(Clone)]
+pub struct Store(Arc<Mutex<Connection>>);
+
+impl Store {
+    pub fn open(path: &Path) -> eyre::Result<Store> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut conn =
+            Connection::open(path).with_context(|| format!("failed to open metadata database {}", path.display()))?;
+
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        migrate(&mut conn)?;
+
+        Ok(Store(Arc::new(Mutex::new(conn))))
+    }
+
+    pub async fn get(&self, name: &str) -> eyre::Result<Option<Image>> {
+        let name = name.to_owned();
+        self.run(move |conn| {
+            conn.query_row("SELECT * FROM images WHERE name = ?1", [name], from_row)
+                .optional()
+        })
+        .await
+    }
+
+    pub async fn put(&self, image: &Image) -> eyre::Result<()> {
+        let image = image.clone();
+        self.run(move |conn| insert(conn, "INSERT OR REPLACE", &image).map(|_| ()))
+            .await
+    }
+
+    pub async fn reserve(&self, image: &Image) -> eyre::Result<bool> {
+        let image = image.clone();
+        self.run(move |conn| match insert(conn, "INSERT", &image) {
+            Ok(_) => Ok(true),
+            Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == ErrorCode::ConstraintViolation => Ok(false),
+            Err(e) => Err(e),
+        })
+        .await
+    }
+
+    pub async fn find_by_hash(&self, sha256: &str, uploader: Option<&str>) -> eyre::Result<Option<Image>> {
+        let (sha256, uploader) = (sha256.to_owned(), uploader.map(ToOwned::to_owned));
+        self.run(move |conn| {
+            conn.query_row(
+                "SELECT * FROM images WHERE sha256 = ?1 AND alias_of IS NULL AND (?2 IS NULL OR uploader = ?2)
+                 ORDER BY (expires_at IS NOT NULL OR max_views IS NOT NULL OR private OR password IS NOT NULL), uploaded_at LIMIT 1",
+                params![sha256, uploader],
+                from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    pub async fn aliases(&self, image: &Image) -> eyre::Result<Vec<Image>> {
+        let name = image.name.clone();
+        self.run(move |conn| {
+            conn.prepare("SELECT * FROM images WHERE alias_of = ?1 ORDER BY uploaded_at")?
+                .query_map([name], from_row)?
+                .collect()
+        })
+        .await
+    }
+
+    pub async fn view(&self, name: &str) -> eyre::Result<Option<u64>> {
+        let name = name.to_owned();
+        self.run(move |conn| {
+            conn.query_row(
+                "UPDATE images SET views = views + 1 WHERE name = ?1 AND views < max_views RETURNING max_views - views",
+                [name],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+        .await
+    }
+
+    pub async fn preview(&self, name: &str, allowance: u64) -> eyre::Result<Option<u64>> {
+        let name = name.to_owned();
+        self.run(move |conn| {
+            conn.query_row(
+                "UPDATE images SET preview_views = preview_views + 1 WHERE name = ?1 AND preview_views < ?2 RETURNING ?2 - preview_views",
+                params![name, allowance],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+        .await
+    }
+
+    pub async fn expired(&self, now: DateTime<Utc>) -> eyre::Result<Vec<Image>> {
+        self.run(move |conn| {
+            conn.prepare("SELECT * FROM images WHERE expires_at <= ?1 OR views >= max_views ORDER BY expires_at")?
+                .query_map([now.timestamp_millis()], from_row)?
+                .collect()
+        })
+        .await
+    }
+
+    pub async fn list(&self, after: Option<(i64, String)>, descending: bool, limit: usize) -> eyre::Result<Vec<Image>> {
+        self.run(move |conn| {
+            let query = match descending {
+                true => {
+                    "SELECT * FROM images WHERE ?1 IS NULL OR (uploaded_at, name) < (?1, ?2)
+                     ORDER BY uploaded_at DESC, name DESC LIMIT ?3"
+                }
+
+                false => {
+                    "SELECT * FROM images WHERE ?1 IS NULL OR (uploaded_at, name) > (?1, ?2)
+                     ORDER BY uploaded_at, name LIMIT ?3"
+                }
+            };
+
+            let (uploaded_at, name) = after.unzip();
+            conn.prepare(query)?
+                .query_map(params![uploaded_at, name, limit], from_row)?
+                .collect()
+        })
+        .await
+    }
+
+    pub async fn delete(&self, name: &str) -> eyre::Result<()> {
+        let name = name.to_owned();
+        self.run(move |conn| conn.execute("DELETE FROM images WHERE name = ?1", [name]).map(|_| ()))
+            .await
+    }
+
+    pub async fn get_album(&self, id: &str) -> eyre::Result<Option<Album>> {
+        let id = id.to_owned();
+        self.run(move |conn| {
+            let Some(mut album) = conn
+                .query_row("SELECT * FROM albums WHERE id = ?1", [&id], |row| {
+                    Ok(Album {
+                        id: row.get("id")?,
+                        title: row.get("title")?,
+                        uploader: row.get("uploader")?,
+                        created_at: DateTime::from_timestamp_millis(row.get("created_at")?).unwrap_or_default(),
+                        images: Vec::new(),
+                    })
+                })
+                .optional()?
+            else {
+                return Ok(None);
+            };
+
+            album.images = conn
+                .prepare("SELECT name, caption FROM album_images WHERE album = ?1 ORDER BY position")?
+                .query_map([&id], |row| {
+                    Ok(AlbumImage {
+                        name: row.get("name")?,
+                        caption: row.get("caption")?,
+                    })
+                })?
+                .collect::<rusqlite::Result<_>>()?;
+
+            Ok(Some(album))
+        })
+        .await
+    }
+
+    pub async fn reserve_album(&self, album: &Album) -> eyre::Result<bool> {
+        let album = album.clone();
+        self.run(move |conn| match insert_album(conn, &album) {
+            Ok(()) => Ok(true),
+            Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == ErrorCode::ConstraintViolation => Ok(false),
+            Err(e) => Err(e),
+        })
+        .await
+    }
+
+    /// Runs `f` on a blocking thread since SQLite does blocking I/O.
+    async fn run<T, F>(&self, f: F) -> eyre::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = Arc::clone(&self.0);
+        tokio::task::spawn_blocking(move || f(&conn.lock().unwrap_or_else(PoisonError::into_inner)))
+            .await?
+            .map_err(Into::into)
+    }
+}
+
+/// Inserts `image` with `verb`, which is either `INSERT` or `INSERT OR REPLACE`.
+fn insert(conn: &Connection, verb: &str, image: &Image) -> rusqlite::Result<usize> {
+    conn.execute(
+        &format!(
+            "{verb} INTO images (name, uploader, original_filename, uploaded_at, size, width, height, sha256, content_type, alias_of, expires_at, max_views, views, preview_views, private, password)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)"
+        ),
+        params![
+            image.name,
+            image.uploader,
+            image.original_filename,
+            image.uploaded_at.timestamp_millis(),
+            image.size,
+            image.width,
+            image.height,
+            image.sha256,
+            image.content_type,
+            image.alias_of,
+            image.expires_at.map(|expires_at| expires_at.timestamp_millis()),
+            image.max_views,
+            image.views,
+            image.preview_views,
+            image.private,
+            image.password,
+        ],
+    )
+}
+
+/// Inserts `album` along with its images, which fails if an album already has the same ID.
+fn insert_album(conn: &Connection, album: &Album) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "INSERT INTO albums (id, title, uploader, created_at) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            album.id,
+            album.title,
+            album.uploader,
+            album.created_at.timestamp_millis()
+        ],
+    )?;
+
+    for (position, image) in album.images.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO album_images (album, position, name, caption) VALUES (?1, ?2, ?3, ?4)",
+            params![album.id, position, image.name, image.caption],
+        )?;
+    }
+
+    tx.commit()
+}
+
+fn migrate(conn: &mut Connection) -> eyre::Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        bail!("metadata database was created by a newer version of ume (schema version {version})");
+    }
+
+    let tx = conn.transaction()?;
+    for (idx, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        tx.execute_batch(migration)
+            .with_context(|| format!("failed to run metadata migration #{}", idx + 1))?;
+
+        tx.pragma_update(None, "user_version", idx + 1)?;
+    }
+
+    tx.commit().map_err(Into::into)
+}
+
+fn from_row(row: &Row<'_>) -> rusqlite::Result<Image> {
+    Ok(Image {
+        name: row.get("name")?,
+        uploader: row.get("uploader")?,
+        original_filename: row.get("original_filename")?,
+        uploaded_at: DateTime::from_timestamp_millis(row.get("uploaded_at")?).unwrap_or_default(),
+        size: row.get("size")?,
+        width: row.get("width")?,
+        height: row.get("height")?,
+        sha256: row.get("sha256")?,
+        content_type: row.get("content_type")?,
+        alias_of: row.get("alias_of")?,
+        expires_at: row
+            .get::<_, Option<i64>>("expires_at")?
+            .and_then(DateTime::from_timestamp_millis),
+        max_views: row.get("max_views")?,
+        views: row.get("views")?,
+        preview_views: row.get("preview_views")?,
+        private: row.get("private")?,
+        password: row.get("password")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Album, AlbumImage, Image, Store};
+    use chrono::{SubsecRound, TimeDelta, Utc};
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn roundtrip() {
+        let store = Store::open(Path::new(":memory:")).unwrap();
+        let image = Image {
+            name: String::from("abcdef.png"),
+            uploader: Some(String::from("noel")),
+            original_filename: Some(String::from("screenshot.png")),
+            uploaded_at: Utc::now().trunc_subsecs(3),
+            size: 1024,
+            width: Some(64),
+            height: Some(48),
+            sha256: String::from("00"),
+            content_type: String::from("image/png"),
+            alias_of: None,
+            expires_at: None,
+            max_views: None,
+            views: 0,
+            preview_views: 0,
+            private: true,
+            password: Some(String::from("$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA")),
+        };
+
+        store.put(&image).await.unwrap();
+
+        let found = store.get("abcdef.png").await.unwrap().unwrap();
+        assert_eq!(found.uploader, image.uploader);
+        assert_eq!(found.uploaded_at, image.uploaded_at);
+        assert_eq!(found.width, Some(64));
+        assert!(found.private);
+        assert_eq!(found.password, image.password);
+
+        let alias = Image {
+            name: String::from("ghijkl.png"),
+            alias_of: Some(image.name.clone()),
+            ..image.clone()
+        };
+
+        // names can only be reserved once, unlike records that are put
+        assert!(store.reserve(&alias).await.unwrap());
+        let taken = Image {
+            views: 1,
+            ..alias.clone()
+        };
+
+        assert!(!store.reserve(&taken).await.unwrap());
+        assert_eq!(store.get("ghijkl.png").await.unwrap().unwrap().views, 0);
+
+        assert_eq!(
+            store.find_by_hash("00", None).await.unwrap().unwrap().name,
+            "abcdef.png"
+        );
+        assert!(store.find_by_hash("00", Some("noel")).await.unwrap().is_some());
+        assert!(store.find_by_hash("00", Some("someone")).await.unwrap().is_none());
+        assert_eq!(store.aliases(&image).await.unwrap().len(), 1);
+
+        let expiring = Image {
+            name: String::from("mnopqr.png"),
+            expires_at: Some(image.uploaded_at + TimeDelta::hours(1)),
+            ..image.clone()
+        };
+
+        store.put(&expiring).await.unwrap();
+        assert!(store.expired(image.uploaded_at).await.unwrap().is_empty());
+
+        let expired = store.expired(image.uploaded_at + TimeDelta::hours(2)).await.unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].expires_at, expiring.expires_at);
+
+        let limited = Image {
+            name: String::from("stuvwx.png"),
+            max_views: Some(2),
+            ..image.clone()
+        };
+
+        store.put(&limited).await.unwrap();
+        assert_eq!(store.view("stuvwx.png").await.unwrap(), Some(1));
+        assert_eq!(store.view("stuvwx.png").await.unwrap(), Some(0));
+        assert_eq!(store.view("stuvwx.png").await.unwrap(), None);
+        assert!(store.get("stuvwx.png").await.unwrap().unwrap().expired());
+
+        let names = |images: Vec<Image>| images.into_iter().map(|image| image.name).collect::<Vec<_>>();
+        assert_eq!(
+            names(store.list(None, false, 2).await.unwrap()),
+            ["abcdef.png", "ghijkl.png"]
+        );
+
+        let after = Some((image.uploaded_at.timestamp_millis(), String::from("ghijkl.png")));
+        assert_eq!(
+            names(store.list(after.clone(), false, 10).await.unwrap()),
+            ["mnopqr.png", "stuvwx.png"]
+        );
+
+        assert_eq!(names(store.list(after, true, 10).await.unwrap()), ["abcdef.png"]);
+
+        store.delete("abcdef.png").await.unwrap();
+        assert!(store.get("abcdef.png").await.unwrap().is_none());
+
+        // images that can be shared are found before the ones that can't, even if they
+        // were uploaded later
+        let public = Image {
+            name: String::from("yzabcd.png"),
+            sha256: String::from("11"),
+            uploaded_at: image.uploaded_at + TimeDelta::hours(1),
+            private: false,
+            password: None,
+            ..image.clone()
+        };
+
+        let expiring = Image {
+            sha256: String::from("11"),
+            ..expiring
+        };
+
+        store.put(&expiring).await.unwrap();
+        store.put(&public).await.unwrap();
+        assert_eq!(
+            store.find_by_hash("11", None).await.unwrap().unwrap().name,
+            "yzabcd.png"
+        );
+    }
+
+    #[tokio::test]
+    async fn albums() {
+        let store = Store::open(Path::new(":memory:")).unwrap();
+        assert!(store.get_album("abcdef").await.unwrap().is_none());
+
+        let mut album = Album {
+            id: String::from("abcdef"),
+            title: Some(String::from("release")),
+            uploader: Some(String::from("noel")),
+            created_at: Utc::now().trunc_subsecs(3),
+            images: vec![
+                AlbumImage {
+                    name: String::from("b.png"),
+                    caption: Some(String::from("banner")),
+                },
+                AlbumImage {
+                    name: String::from("a.png"),
+                    caption: None,
+                },
+            ],
+        };
+
+        assert!(store.reserve_album(&album).await.unwrap());
+
+        let found = store.get_album("abcdef").await.unwrap().unwrap();
+        assert_eq!(found.title, album.title);
+        assert_eq!(found.created_at, album.created_at);
+        assert_eq!(
+            found.images.iter().map(|image| image.name.as_str()).collect::<Vec<_>>(),
+            ["b.png", "a.png"]
+        );
+
+        assert_eq!(found.images[0].caption.as_deref(), Some("banner"));
+
+        // ids can only be reserved once, and the album that has it is left as it is
+        let images = album.images.split_off(1);
+        assert!(!store.reserve_album(&album).await.unwrap());
+        assert_eq!(store.get_album("abcdef").await.unwrap().unwrap().images.len(), 2);
+
+        album.id = String::from("ghijkl");
+        album.images = images;
+        assert!(store.reserve_album(&album).await.unwrap());
+        let found = store.get_album("ghijkl").await.unwrap().unwrap();
+        assert_eq!(found.images[0].name, "a.png");
+    }
+
+    #[test]
+    fn migrate_twice() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        super::migrate(&mut conn).unwrap();
+        super::migrate(&mut conn).unwrap();
+    }
+}
@@ -22,6 +22,7 @@ use axum::{
 };
 use rand::distr::{Alphanumeric, SampleString};
 use std::{fmt::Display, ops::Deref, time::Instant};
+use tracing::Instrument;
 
 #[derive(FromRequestParts)]
 pub struct Metadata {
@@ -107,16 +108,20 @@ pub async fn log(metadata: Metadata, req: Request<Body>, next: Next) -> impl Int
         req.id = %id,
         http.uri = uri,
         http.method = method,
-        http.version = version
+        http.version = version,
+        uploader = tracing::field::Empty
     );
 
-    let _guard = http_span.enter();
-    info!("processing request");
+    async move {
+        info!("processing request");
 
-    let res = next.run(req).await;
-    let now = start.elapsed();
+        let res = next.run(req).await;
+        let now = start.elapsed();
 
-    info!(duration = ?now, "processed request successfully");
+        info!(duration = ?now, "processed request successfully");
 
-    res
+        res
+    }
+    .instrument(http_span)
+    .await
 }
@@ -16,9 +16,28 @@
 mod config;
 pub use config::*;
 
+mod album;
+mod auth;
+mod backfill;
+mod cache;
+mod dedup;
+mod deletion;
+mod expiry;
 mod extract;
+mod fetch;
+mod metadata;
 mod middleware;
+mod naming;
+mod password;
 mod routes;
+mod sanitize;
+mod signing;
+mod spool;
+mod stream;
+mod svg;
+mod thumbnail;
+mod transform;
+mod views;
 
 use axum::{
     Extension, Router,
@@ -31,34 +50,77 @@ use axum_server::{Handle, tls_rustls::RustlsConfig};
 use azalia::remi::StorageService;
 use eyre::Context;
 use serde_json::json;
-use std::{any::Any, time::Duration};
+use std::{any::Any, net::SocketAddr, time::Duration};
 
 pub fn create_router() -> Router {
     Router::new()
         .route("/heartbeat", routing::get(routes::heartbeat))
+        .route("/images", routing::get(routes::list_images).put(routes::put_image))
         .route("/images/upload", routing::post(routes::upload_image))
-        .route("/images/{name}", routing::get(routes::get_image))
+        .route("/images/fetch", routing::post(routes::fetch_image))
+        .route(
+            "/images/{name}",
+            routing::get(routes::get_image)
+                .post(routes::unlock_image)
+                .put(routes::put_image)
+                .delete(routes::delete_image),
+        )
+        .route("/images/{name}/delete", routing::get(routes::delete_image))
+        .route("/images/{name}/info", routing::get(routes::get_image_info))
+        .route("/images/{name}/sign", routing::post(routes::sign_image))
+        .route(
+            "/images/{name}/thumbnail",
+            routing::get(routes::get_thumbnail).post(routes::unlock_image),
+        )
+        .route("/albums/{id}", routing::get(routes::get_album))
         .route("/", routing::get(routes::main))
 }
 
-/// Starts a Ume server with the configured [`StorageService`] and loaded configuration file.
-pub async fn start_server(storage: StorageService, config: crate::config::Config) -> eyre::Result<()> {
+/// Starts a Ume server with the storage service and loaded configuration file.
+pub async fn start_server(config: crate::config::Config) -> eyre::Result<()> {
     info!("starting Ume server!");
 
-    let router = create_router()
+    let (storage, streamer) = stream::Streamer::new(&config.storage)?;
+    <StorageService as azalia::remi::core::StorageService>::init(&storage).await?;
+
+    let metadata = metadata::Store::new(&config.metadata, &storage)?;
+    expiry::spawn(storage.clone(), metadata.clone(), config.uploads.thumbnails.clone());
+    backfill::spawn(storage.clone(), metadata.clone());
+
+    let router = create_app(storage, streamer, metadata, &config)?;
+    match config.server.ssl {
+        Some(ref ssl) => start_https_server(&config.server, ssl, router.clone()).await,
+        None => start_http_server(&config.server, router).await,
+    }
+}
+
+/// Creates the router along with every layer and extension that its routes use.
+fn create_app(
+    storage: StorageService,
+    streamer: stream::Streamer,
+    metadata: metadata::Store,
+    config: &crate::config::Config,
+) -> eyre::Result<Router> {
+    let pool = transform::Pool::new(config.transforms.max_concurrency);
+    let fetcher = fetch::Fetcher::new(&config.fetch)?;
+    let authenticator = auth::Authenticator::new(config);
+    let unlocker = password::Unlocker::new(config);
+
+    Ok(create_router()
         .layer(sentry_tower::NewSentryLayer::new_from_top())
         .layer(sentry_tower::SentryHttpLayer::new().enable_transaction())
         .layer(tower_http::catch_panic::CatchPanicLayer::custom(panic_handler))
-        .layer(DefaultBodyLimit::max(15 * 1024 * 1024))
+        .layer(DefaultBodyLimit::max(config.server.body_limit))
         .layer(axum::middleware::from_fn(crate::server::middleware::log))
         .layer(axum::middleware::from_fn(crate::server::middleware::request_id))
         .layer(Extension(storage))
-        .layer(Extension(config.clone()));
-
-    match config.server.ssl {
-        Some(ref ssl) => start_https_server(&config.server, ssl, router.clone()).await,
-        None => start_http_server(&config.server, router).await,
-    }
+        .layer(Extension(streamer))
+        .layer(Extension(metadata))
+        .layer(Extension(pool))
+        .layer(Extension(fetcher))
+        .layer(Extension(authenticator))
+        .layer(Extension(unlocker))
+        .layer(Extension(config.clone())))
 }
 
 async fn start_https_server(config: &Config, ssl: &config::ssl::Config, router: Router) -> eyre::Result<()> {
@@ -77,7 +139,7 @@ async fn start_https_server(config: &Config, ssl: &config::ssl::Config, router:
 
     axum_server::bind_rustls(addr, config)
         .handle(handle)
-        .serve(router.into_make_service())
+        .serve(router.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .context("failed to run HTTPS server")
 }
@@ -87,7 +149,7 @@ async fn start_http_server(config: &Config, router: Router) -> eyre::Result<()>
     let listener = tokio::net::TcpListener::bind(addr).await?;
     info!(address = ?addr, "listening on HTTP");
 
-    axum::serve(listener, router.into_make_service())
+    axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>())
         .with_graceful_shutdown(shutdown_signal(None))
         .await
         .context("failed to run HTTP server")
@@ -0,0 +1,255 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::metadata::{self, Album, Image};
+use crate::{
+    config::naming::{Config, Strategy},
+    format::ImageFormat,
+};
+use azalia::remi::{StorageService, core::StorageService as _};
+use chrono::Utc;
+use rand::{Rng, seq::IndexedRandom};
+use uuid::Uuid;
+
+/// How many names are generated before giving up when every one of them is taken.
+const ATTEMPTS: usize = 10;
+
+/// Longest name (without its extension) that uploaders can ask for.
+const MAX_VANITY_LENGTH: usize = 64;
+
+/// Length of the random suffix that is added to `timestamp` and `content-hash` names
+/// that are already taken.
+const SUFFIX_LENGTH: usize = 4;
+
+const ADJECTIVES: &[&str] = &[
+    "able", "bold", "brave", "bright", "busy", "calm", "clever", "cozy", "crisp", "curious", "cute", "daring", "eager",
+    "early", "fancy", "fast", "fluffy", "fond", "fresh", "friendly", "funny", "gentle", "giant", "glad", "golden",
+    "grand", "happy", "hidden", "honest", "humble", "jolly", "kind", "lazy", "lively", "lucky", "merry", "mighty",
+    "modest", "noble", "odd", "patient", "plucky", "polite", "proud", "quick", "quiet", "rapid", "rare", "shiny",
+    "shy", "silly", "sleepy", "smooth", "snowy", "soft", "sunny", "swift", "tidy", "tiny", "warm", "wild", "wise",
+    "witty", "zesty",
+];
+
+const COLOURS: &[&str] = &[
+    "amber", "azure", "beige", "black", "blue", "bronze", "brown", "coral", "crimson", "cyan", "gold", "gray", "green",
+    "indigo", "ivory", "jade", "lavender", "lilac", "lime", "magenta", "maroon", "mint", "navy", "olive", "orange",
+    "peach", "pink", "purple", "red", "silver", "teal", "white",
+];
+
+const ANIMALS: &[&str] = &[
+    "alpaca", "badger", "bat", "bear", "beaver", "bee", "bison", "cat", "cheetah", "crab", "crow", "deer", "dolphin",
+    "duck", "eagle", "eel", "falcon", "ferret", "finch", "fox", "frog", "gecko", "goat", "goose", "hare", "hawk",
+    "hedgehog", "heron", "ibis", "koala", "lemur", "lion", "llama", "lynx", "mole", "moose", "moth", "newt", "otter",
+    "owl", "panda", "parrot", "penguin", "pigeon", "puffin", "quail", "rabbit", "raccoon", "raven", "robin", "salmon",
+    "seal", "shark", "sloth", "snail", "sparrow", "squid", "stoat", "swan", "tiger", "toad", "turtle", "walrus",
+    "wolf",
+];
+
+/// Generates a name with the extension `ext` for `image`, which isn't used by any other
+/// image yet, and records `image` under it so that no other upload can take it.
+pub async fn generate(
+    config: &Config,
+    storage: &StorageService,
+    metadata: &metadata::Store,
+    image: &Image,
+    ext: &str,
+) -> eyre::Result<String> {
+    for attempt in 0..ATTEMPTS {
+        let name = format!("{}.{ext}", candidate(config, &image.sha256, attempt > 0));
+        let image = Image {
+            name: name.clone(),
+            ..image.clone()
+        };
+
+        if reserve(storage, metadata, &image).await? {
+            return Ok(name);
+        }
+
+        debug!(file = %name, "generated name is already taken, trying another one");
+    }
+
+    bail!("unable to generate a name that isn't taken after {ATTEMPTS} attempts")
+}
+
+/// Generates a short ID for `album` that isn't used by any other album yet, and records
+/// `album` under it so that no other album can take it.
+pub async fn album(config: &Config, metadata: &metadata::Store, album: &Album) -> eyre::Result<String> {
+    for _ in 0..ATTEMPTS {
+        let id = random(&mut rand::rng(), &config.alphabet, config.length);
+        let album = Album {
+            id: id.clone(),
+            ..album.clone()
+        };
+
+        if metadata.reserve_album(&album).await? {
+            return Ok(id);
+        }
+
+        debug!(album = %id, "generated album id is already taken, trying another one");
+    }
+
+    bail!("unable to generate an album id that isn't taken after {ATTEMPTS} attempts")
+}
+
+/// Validates the name that the uploader asked for an image in `format` to have, like
+/// `release-banner` or `release-banner.png`, and returns it with the format's extension.
+pub fn vanity(requested: &str, format: ImageFormat) -> Result<String, String> {
+    let requested = requested.trim();
+    let ext = format.extension();
+    let stem = match requested.rsplit_once('.') {
+        Some((stem, given))
+            if given.eq_ignore_ascii_case(ext)
+                || (format == ImageFormat::Jpeg && given.eq_ignore_ascii_case("jpeg")) =>
+        {
+            stem
+        }
+
+        Some((_, given)) if given.chars().all(|ch| ch.is_ascii_alphanumeric()) => {
+            return Err(format!("`{requested}` has to end with `.{ext}` to match the image"));
+        }
+
+        _ => requested,
+    };
+
+    if stem.is_empty() || stem.len() > MAX_VANITY_LENGTH {
+        return Err(format!(
+            "names have to be between 1 and {MAX_VANITY_LENGTH} characters long"
+        ));
+    }
+
+    // names end up in URLs and storage paths, so they can't have anything that would
+    // need to be escaped or that could make a path like `..`
+    if !stem.starts_with(|ch: char| ch.is_ascii_alphanumeric())
+        || !stem
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
+    {
+        return Err(String::from(
+            "names can only have ASCII letters, digits, `-` and `_`, and have to start with a letter or digit",
+        ));
+    }
+
+    Ok(format!("{stem}.{ext}"))
+}
+
+/// Records `image` unless an image with the same name already exists, and returns whether
+/// it was recorded.
+pub async fn reserve(storage: &StorageService, metadata: &metadata::Store, image: &Image) -> eyre::Result<bool> {
+    // images that were uploaded before metadata was recorded only exist in the storage
+    // service, and are never uploaded again
+    if storage.exists(format!("./{}", image.name)).await? {
+        return Ok(false);
+    }
+
+    metadata.reserve(image).await
+}
+
+fn candidate(config: &Config, sha256: &str, retry: bool) -> String {
+    let mut rng = rand::rng();
+    let name = match config.strategy {
+        Strategy::Random => return random(&mut rng, &config.alphabet, config.length),
+        Strategy::Uuidv7 => return Uuid::now_v7().to_string(),
+        Strategy::Words => return words(&mut rng, config.words),
+        Strategy::Timestamp => Utc::now().format("%Y%m%d-%H%M%S").to_string(),
+        Strategy::ContentHash => sha256[..config.length.min(sha256.len())].to_owned(),
+    };
+
+    // these are the same for every upload in the same second or of the same image, so
+    // a random suffix is the only way to get a different name
+    match retry {
+        true => format!("{name}-{}", random(&mut rng, &config.alphabet, SUFFIX_LENGTH)),
+        false => name,
+    }
+}
+
+fn random(rng: &mut impl Rng, alphabet: &str, length: usize) -> String {
+    // the alphabet is validated to only have ASCII characters
+    let alphabet = alphabet.as_bytes();
+    (0..length)
+        .filter_map(|_| alphabet.choose(rng).map(|&ch| char::from(ch)))
+        .collect()
+}
+
+/// Picks `count` words, which are adjectives except for the last two, which are a colour
+/// and an animal.
+fn words(rng: &mut impl Rng, count: usize) -> String {
+    (0..count)
+        .rev()
+        .filter_map(|left| {
+            let list = match left {
+                0 => ANIMALS,
+                1 => COLOURS,
+                _ => ADJECTIVES,
+            };
+
+            list.choose(rng).copied()
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::naming::{Config, Strategy},
+        format::ImageFormat,
+    };
+
+    #[test]
+    fn candidate() {
+        let config = Config {
+            alphabet: String::from("ab"),
+            length: 8,
+            ..Config::default()
+        };
+
+        let name = super::candidate(&config, "", false);
+        assert_eq!(name.len(), 8);
+        assert!(name.chars().all(|ch| ch == 'a' || ch == 'b'));
+
+        let config = Config {
+            strategy: Strategy::Words,
+            ..Config::default()
+        };
+
+        let name = super::candidate(&config, "", false);
+        assert_eq!(name.split('-').count(), 3);
+        assert!(super::ANIMALS.contains(&name.rsplit('-').next().unwrap()));
+
+        let config = Config {
+            strategy: Strategy::ContentHash,
+            alphabet: String::from("x"),
+            ..Config::default()
+        };
+
+        assert_eq!(super::candidate(&config, "0123456789abcdef", false), "012345");
+        assert_eq!(super::candidate(&config, "0123456789abcdef", true), "012345-xxxx");
+    }
+
+    #[test]
+    fn vanity() {
+        assert_eq!(
+            super::vanity("release-banner", ImageFormat::Png).as_deref(),
+            Ok("release-banner.png")
+        );
+
+        assert_eq!(super::vanity("cat.JPEG", ImageFormat::Jpeg).as_deref(), Ok("cat.jpg"));
+        assert!(super::vanity("cat.gif", ImageFormat::Png).is_err());
+        assert!(super::vanity("../cat", ImageFormat::Png).is_err());
+        assert!(super::vanity("-cat", ImageFormat::Png).is_err());
+        assert!(super::vanity(".png", ImageFormat::Png).is_err());
+        assert!(super::vanity(&"a".repeat(65), ImageFormat::Png).is_err());
+    }
+}
@@ -0,0 +1,348 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{signing, transform};
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use axum::{
+    Json,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{Html, IntoResponse, Response},
+};
+use chrono::Utc;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+use url::Url;
+
+/// Header that the password of a password-protected image can be sent in, instead of the
+/// `password` query parameter (which ends up in access logs and browser history).
+pub const HEADER: &str = "x-ume-password";
+
+/// Name of the cookie that remembers that the right password was given for an image, so
+/// that it doesn't have to be verified again on every view.
+const COOKIE: &str = "ume-unlocked";
+
+/// How long the cookie that [`Unlocker::unlock`] sets is valid for.
+const COOKIE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// How many passwords can be verified at the same time. Like with hashed uploader keys,
+/// requests with made up passwords can't be allowed to use as much CPU and memory as they
+/// want.
+const VERIFY_CONCURRENCY: usize = 4;
+
+/// How many wrong passwords can be given for an image from the same address before it has
+/// to wait for [`ATTEMPTS_WINDOW`] to pass.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// How many wrong passwords can be given for an image from every address before all of
+/// them have to wait for [`ATTEMPTS_WINDOW`] to pass, so that guessing from many addresses
+/// doesn't get around [`MAX_ATTEMPTS`].
+const MAX_IMAGE_ATTEMPTS: u32 = 50;
+
+/// How long wrong passwords are remembered for.
+const ATTEMPTS_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// How many images and addresses that gave a wrong password are remembered at most.
+const ATTEMPTS_CAPACITY: usize = 4096;
+
+/// Checks whether `password` matches `hash`, which was created by
+/// [`hash_key`][crate::config::hash_key].
+///
+/// Argon2 is intentionally slow, so this should be called from a blocking context.
+pub fn verify(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
+
+/// What wrong passwords are counted against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Attempt {
+    /// An image, along with the address that they were given from.
+    Address(String, Option<IpAddr>),
+
+    /// An image, from every address.
+    Image(String),
+}
+
+impl Attempt {
+    const fn limit(&self) -> u32 {
+        match self {
+            Attempt::Address(..) => MAX_ATTEMPTS,
+            Attempt::Image(_) => MAX_IMAGE_ATTEMPTS,
+        }
+    }
+}
+
+/// How many wrong passwords were given for each [`Attempt`], and when the first one was.
+type Attempts = HashMap<Attempt, (u32, Instant)>;
+
+/// Verifies the passwords of password-protected images. This is cheap to clone and is
+/// available as an extension.
+#[derive(Clone)]
+pub struct Unlocker(Arc<Inner>);
+
+struct Inner {
+    /// Key that the cookies are signed with.
+    key: String,
+    base_url: Url,
+
+    /// Limits how many passwords are verified at the same time.
+    pool: transform::Pool,
+
+    /// Wrong passwords that were given recently.
+    attempts: Mutex<Attempts>,
+}
+
+impl Unlocker {
+    pub fn new(config: &crate::config::Config) -> Self {
+        Unlocker(Arc::new(Inner {
+            key: config.secret_key.clone(),
+            base_url: config.base_url.clone(),
+            pool: transform::Pool::new(VERIFY_CONCURRENCY),
+            attempts: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// Checks that the request for the image called `image`, which is protected by the
+    /// password that `hash` is the hash of, came with a cookie that was set by an earlier
+    /// request or with the right password. If it came with the right password, then this
+    /// returns the `Set-Cookie` header that lets the next requests skip verifying it.
+    ///
+    /// Otherwise, this returns the response that asks for the password, or that tells the
+    /// client to wait if too many wrong passwords were given for the image already.
+    pub async fn unlock(
+        &self,
+        image: &str,
+        hash: &str,
+        headers: &HeaderMap,
+        address: Option<IpAddr>,
+        password: Option<&str>,
+    ) -> Result<Option<HeaderValue>, Response> {
+        if self.has_cookie(headers, image, hash) {
+            return Ok(None);
+        }
+
+        let Some(password) = given(headers, password) else {
+            return Err(prompt(headers, false));
+        };
+
+        let attempts = [
+            Attempt::Address(image.to_owned(), address),
+            Attempt::Image(image.to_owned()),
+        ];
+
+        if let Some(wait) = attempts.iter().filter_map(|attempt| self.wait(attempt)).max() {
+            return Err(too_many_attempts(wait));
+        }
+
+        let matches = {
+            let hash = hash.to_owned();
+            self.0.pool.run(move || verify(&hash, &password)).await
+        };
+
+        if !matches {
+            for attempt in attempts {
+                self.fail(attempt);
+            }
+
+            return Err(prompt(headers, true));
+        }
+
+        // the wrong passwords of other addresses still count against the image
+        let [address, _] = attempts;
+        self.attempts().remove(&address);
+
+        Ok(self.cookie(image, hash))
+    }
+
+    /// Returns how long `attempt` has to wait before it can try another password, if it
+    /// gave too many wrong ones already.
+    fn wait(&self, attempt: &Attempt) -> Option<Duration> {
+        let (failed, since) = *self.attempts().get(attempt)?;
+        let wait = ATTEMPTS_WINDOW.checked_sub(since.elapsed())?;
+
+        (failed >= attempt.limit()).then_some(wait)
+    }
+
+    fn fail(&self, attempt: Attempt) {
+        let mut attempts = self.attempts();
+        if attempts.len() >= ATTEMPTS_CAPACITY {
+            attempts.retain(|_, (_, since)| since.elapsed() < ATTEMPTS_WINDOW);
+            if attempts.len() >= ATTEMPTS_CAPACITY {
+                attempts.clear();
+            }
+        }
+
+        let (failed, since) = attempts.entry(attempt).or_insert((0, Instant::now()));
+        if since.elapsed() >= ATTEMPTS_WINDOW {
+            (*failed, *since) = (0, Instant::now());
+        }
+
+        *failed += 1;
+    }
+
+    fn attempts(&self) -> MutexGuard<'_, Attempts> {
+        self.0.attempts.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Creates the cookie that remembers that the right password was given for `image`.
+    /// The cookie is signed along with the password's hash, so it stops working if the
+    /// password changes.
+    fn cookie(&self, image: &str, hash: &str) -> Option<HeaderValue> {
+        let expires = Utc::now().timestamp().saturating_add_unsigned(COOKIE_TTL.as_secs());
+        let sig = signing::sign_until(&self.0.key, image, &[("password", hash.to_owned())], expires);
+
+        // the cookie is only sent along with requests for the image, its thumbnail and
+        // its information
+        let url = self.0.base_url.join(&format!("images/{image}")).ok()?;
+        let secure = match url.scheme() {
+            "https" => "; Secure",
+            _ => "",
+        };
+
+        HeaderValue::from_str(&format!(
+            "{COOKIE}={expires}.{sig}; Path={}; Max-Age={}; HttpOnly; SameSite=Lax{secure}",
+            url.path(),
+            COOKIE_TTL.as_secs()
+        ))
+        .ok()
+    }
+
+    fn has_cookie(&self, headers: &HeaderMap, image: &str, hash: &str) -> bool {
+        headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|cookie| cookie.trim().strip_prefix(COOKIE)?.strip_prefix('='))
+            .filter_map(|value| value.split_once('.'))
+            .any(|(expires, sig)| {
+                expires.parse().is_ok_and(|expires| {
+                    signing::verify_until(&self.0.key, image, &[("password", hash.to_owned())], expires, sig)
+                })
+            })
+    }
+}
+
+/// Response for requests that gave too many wrong passwords.
+fn too_many_attempts(wait: Duration) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, HeaderValue::from(wait.as_secs().max(1)))],
+        Json(json!({
+            "message": "too many wrong passwords, pls try again later"
+        })),
+    )
+        .into_response()
+}
+
+/// Returns the password that was sent with the request, either in the [`HEADER`] header
+/// or in the `password` query parameter.
+pub fn given(headers: &HeaderMap, query: Option<&str>) -> Option<String> {
+    headers
+        .get(HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+        .or_else(|| query.map(str::to_owned))
+        .filter(|password| !password.is_empty())
+}
+
+/// Rejects a request for a password-protected image that came without the right password.
+///
+/// Browsers get a small page that asks for the password and posts it back to the same URL
+/// (so that the rest of the query, like a transformation or signature, is kept), which
+/// keeps it out of the URL. Everything else gets a JSON error.
+pub fn prompt(headers: &HeaderMap, wrong: bool) -> Response {
+    let (status, message) = match wrong {
+        true => (StatusCode::FORBIDDEN, "wrong password, pls try again"),
+        false => (StatusCode::UNAUTHORIZED, "image is protected by a password"),
+    };
+
+    let wants_html = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+
+    if !wants_html {
+        return (status, Json(json!({ "message": message }))).into_response();
+    }
+
+    (
+        status,
+        [
+            (
+                header::CONTENT_SECURITY_POLICY,
+                HeaderValue::from_static("default-src 'none'; style-src 'unsafe-inline'; form-action 'self'"),
+            ),
+            (header::CACHE_CONTROL, HeaderValue::from_static("no-store")),
+        ],
+        Html(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>Password required</title>
+<style>body{{font-family:sans-serif;display:flex;justify-content:center;margin-top:20vh}}form{{display:flex;flex-direction:column;gap:.5rem}}</style>
+</head>
+<body>
+<form method="post">
+<label for="password">{message}</label>
+<input id="password" name="password" type="password" autofocus required>
+<button type="submit">View image</button>
+</form>
+</body>
+</html>
+"#
+        )),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ATTEMPTS_WINDOW, Attempt, MAX_ATTEMPTS, MAX_IMAGE_ATTEMPTS, Unlocker};
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn attempts_are_limited_per_address_and_per_image() {
+        let config: crate::config::Config = toml::from_str(r#"uploader_key = "abc""#).unwrap();
+        let unlocker = Unlocker::new(&config);
+        let address = |n: u32| Attempt::Address(String::from("a.png"), Some(IpAddr::V4(Ipv4Addr::from(n))));
+
+        for _ in 0..MAX_ATTEMPTS {
+            assert!(unlocker.wait(&address(0)).is_none());
+            unlocker.fail(address(0));
+        }
+
+        assert!(unlocker.wait(&address(0)).is_some_and(|wait| wait <= ATTEMPTS_WINDOW));
+        assert!(unlocker.wait(&address(1)).is_none());
+
+        // guessing from a different address every time still runs into the image's limit
+        let image = Attempt::Image(String::from("a.png"));
+        for n in 0..MAX_IMAGE_ATTEMPTS {
+            assert!(unlocker.wait(&image).is_none());
+            unlocker.fail(address(n));
+            unlocker.fail(image.clone());
+        }
+
+        assert!(unlocker.wait(&image).is_some());
+        assert!(unlocker.wait(&Attempt::Image(String::from("b.png"))).is_none());
+    }
+}
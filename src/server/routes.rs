@@ -13,23 +13,47 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::extract::Multipart;
-use axum::{
-    extract::Path,
-    http::{
-        header::{self, AUTHORIZATION},
-        HeaderValue, StatusCode,
+use super::{
+    auth::Uploader,
+    cache::{Conditions, Ranges, Validators},
+    extract::Multipart,
+    fetch::{self, Fetcher},
+    metadata,
+    spool::Spool,
+    stream::{self, Object, Streamer},
+    svg, transform,
+};
+use crate::{
+    config::{
+        transforms::Transform,
+        uploader::Scope,
+        uploads::{Deduplicate, Svg},
     },
-    response::IntoResponse,
-    Extension, Json,
+    format::ImageFormat,
+};
+use axum::{
+    body::{Body, Bytes},
+    extract::{rejection::JsonRejection, ConnectInfo, Path, Query, RawQuery},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
+    Extension, Form, Json, RequestExt,
 };
-use axum_extra::{headers::Header, TypedHeader};
 use azalia::remi::{
-    core::{Blob, StorageService as _, UploadRequest},
+    core::StorageService as _,
     StorageService,
 };
+use axum_extra::headers::{ContentRange, HeaderMapExt};
+use base64::{prelude::BASE64_URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, TryStreamExt};
 use rand::distr::{Alphanumeric, SampleString};
+use serde::Deserialize;
 use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{net::SocketAddr, str::FromStr};
+use tokio::io::AsyncWriteExt as _;
+use tracing::Instrument;
+use url::Url;
 
 pub async fn main() -> Json<Value> {
     Json(json!({
@@ -46,34 +70,35 @@ pub async fn heartbeat() -> &'static str {
     "Ok."
 }
 
-pub struct UploaderKey(pub HeaderValue);
-impl Header for UploaderKey {
-    fn name() -> &'static axum::http::HeaderName {
-        &AUTHORIZATION
-    }
-
-    fn encode<E: Extend<axum::http::HeaderValue>>(&self, values: &mut E) {
-        values.extend(std::iter::once(HeaderValue::from_bytes(self.0.as_ref()).unwrap()));
-    }
-
-    fn decode<'i, I>(values: &mut I) -> Result<Self, axum_extra::headers::Error>
-    where
-        Self: Sized,
-        I: Iterator<Item = &'i HeaderValue>,
-    {
-        values
-            .next()
-            .map(|h| UploaderKey(h.clone()))
-            .ok_or_else(axum_extra::headers::Error::invalid)
-    }
-}
+/// Headers that images are served with, so that scripts in SVG images can't run in our
+/// origin and browsers don't guess a different content type.
+const IMAGE_HEADERS: [(HeaderName, HeaderValue); 2] = [
+    (
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static(
+            "default-src 'none'; style-src 'unsafe-inline'; form-action 'none'; frame-ancestors 'none'",
+        ),
+    ),
+    (header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+];
 
+#[allow(clippy::too_many_arguments)]
 #[instrument(name = "ume.image.get", skip_all)]
 pub async fn get_image(
+    Extension(streamer): Extension<Streamer>,
     Extension(storage): Extension<StorageService>,
+    Extension(metadata): Extension<metadata::Store>,
+    Extension(config): Extension<crate::config::Config>,
+    Extension(pool): Extension<transform::Pool>,
+    Extension(unlocker): Extension<super::password::Unlocker>,
+    address: Option<Extension<ConnectInfo<SocketAddr>>>,
+    method: Method,
+    request_headers: HeaderMap,
     Path(image): Path<String>,
-) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
-    if image.contains("..") {
+    conditions: Conditions,
+    Query(query): Query<transform::Query>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    if image.contains("..") || image.starts_with('.') {
         return Err((
             StatusCode::NOT_FOUND,
             Json(json!({
@@ -82,22 +107,64 @@ pub async fn get_image(
         ));
     }
 
-    let Some(file) = storage
-        .blob(format!("./{image}"))
-        .await
-        .inspect_err(|e| {
-            error!(error = %e, %image, "unable to find the image specified");
-            sentry::capture_error(&e);
-        })
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({
-                    "message": "internal server error, pls try again later"
-                })),
-            )
-        })?
-    else {
+    let internal_error = |e: eyre::Report| {
+        error!(error = %e, %image, "unable to get image");
+        sentry::capture_error::<dyn std::error::Error>(e.as_ref());
+
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "message": "internal server error, pls try again later"
+            })),
+        )
+    };
+
+    let transform = resolve_transform(&config, &image, &query)?;
+    let record = metadata.get(&image).await.map_err(internal_error)?;
+    if let Some(ref record) = record
+        && record.expired()
+    {
+        // no need to wait for the reaper to get to it
+        super::expiry::delete(storage, metadata, config.uploads.thumbnails.clone(), record.clone());
+        return Err(gone());
+    }
+
+    // private images can only be viewed with a signed URL that expires
+    if let Some(ref record) = record
+        && record.private
+        && (query.expires.is_none()
+            || !is_signed(
+                &config,
+                &image,
+                &transform::params(&query.transform()),
+                query.expires,
+                query.sig.as_deref(),
+            ))
+    {
+        return Err(private_image());
+    }
+
+    let cookie = match unlock(
+        &unlocker,
+        record.as_ref(),
+        &request_headers,
+        address,
+        query.password.as_deref(),
+    )
+    .await
+    {
+        Ok(cookie) => cookie,
+        Err(response) => return Ok(response),
+    };
+
+    let limited = record.as_ref().is_some_and(|record| record.max_views.is_some());
+    let private = record
+        .as_ref()
+        .is_some_and(|record| record.private || record.protected());
+
+    // images that were deduplicated into an alias are served from the image they point to
+    let blob = record.as_ref().map_or(image.as_str(), metadata::Image::blob);
+    let Some(mut object) = streamer.stat(&format!("./{blob}")).await.map_err(internal_error)? else {
         return Err((
             StatusCode::NOT_FOUND,
             Json(json!({
@@ -106,159 +173,2552 @@ pub async fn get_image(
         ));
     };
 
-    // we should never reach here, if we do then it is a problem we need to face
-    if let Blob::File(file) = file {
-        let ct = file
-            .content_type
-            .unwrap_or_else(|| azalia::remi::fs::default_resolver(&file.data).to_string());
+    // images that were uploaded before metadata was recorded have to be read to know
+    // what they are
+    let (ct, sha256) = match record {
+        Some(record) => (record.content_type, record.sha256),
+        None => {
+            let data = streamer.bytes(&object).await.map_err(internal_error)?;
+            let ct = object
+                .content_type
+                .take()
+                .unwrap_or_else(|| azalia::remi::fs::default_resolver(&data).to_string());
+
+            let sha256 = format!("{:x}", Sha256::digest(&data));
+            object = Object::from_bytes(data, object.last_modified);
+
+            (ct, sha256)
+        }
+    };
+
+    let mime = ct.parse::<mime::Mime>().map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "message": "unable to infer field data's contents"
+            })),
+        )
+    })?;
+
+    if mime.type_() != mime::IMAGE {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "message": "wanted a image from field data's contents, didn't receive one though..."
+            })),
+        ));
+    }
+
+    if mime.subtype() == mime::SVG && !transform::is_empty(&transform) {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({
+                "message": "svg images can't be transformed"
+            })),
+        ));
+    }
+
+    // images with a view limit can't be kept around by anyone, otherwise they could be
+    // seen again without using up a view. Private and password-protected images can only
+    // be kept by whoever had the signed URL or password.
+    let cache_control = match (limited, private) {
+        (true, _) => "private, no-store",
+        (false, true) => "private",
+        (false, false) => &config.server.cache_control,
+    };
+
+    let validators = Validators::new(&sha256, &transform::params(&transform), object.last_modified);
+    let mut headers = validators.headers(cache_control);
+    if let Some(cookie) = cookie {
+        headers.insert(header::SET_COOKIE, cookie);
+    }
+
+    if validators.not_modified(&conditions) {
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+    }
+
+    let (ct, object) = if transform::is_empty(&transform) {
+        (ct, object)
+    } else {
+        let data = streamer.bytes(&object).await.map_err(internal_error)?;
+        let (data, ct) = pool
+            .apply(data, transform)
+            .await
+            .inspect_err(|e| {
+                error!(error = %e, %image, "unable to transform image");
+            })
+            .map_err(|_| {
+                (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Json(json!({
+                        "message": "unable to transform image"
+                    })),
+                )
+            })?;
+
+        (ct.to_owned(), Object::from_bytes(data.into(), object.last_modified))
+    };
+
+    // `HEAD` requests don't use up a view since they don't get the image, and neither do
+    // bots that fetch it to show a preview of the link while they have previews left
+    if limited && method != Method::HEAD {
+        let allowance = config.uploads.preview_views;
+        let previewed = allowance > 0
+            && super::views::is_preview_bot(&request_headers)
+            && metadata
+                .preview(&image, allowance)
+                .await
+                .map_err(internal_error)?
+                .is_some();
+
+        if !previewed && metadata.view(&image).await.map_err(internal_error)?.is_none() {
+            return Err(gone());
+        }
+    }
+
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+    // `HEAD` requests get the same headers without reading the image
+    let head = method == Method::HEAD;
+    let (status, ct, length, body) = match validators.ranges(&conditions, object.size) {
+        Ranges::All => {
+            let body = match head {
+                true => None,
+                false => Some(streamer.read(&object, None).await.map_err(internal_error)?),
+            };
+
+            (StatusCode::OK, ct, object.size, body)
+        }
+
+        Ranges::Partial(mut ranges) if ranges.len() == 1 => {
+            let range = ranges.remove(0);
+            headers.typed_insert(ContentRange::bytes(range.clone(), object.size).unwrap());
+
+            let length = range.end() - range.start() + 1;
+            let body = match head {
+                true => None,
+                false => Some(streamer.read(&object, Some(range)).await.map_err(internal_error)?),
+            };
+
+            (StatusCode::PARTIAL_CONTENT, ct, length, body)
+        }
+
+        Ranges::Partial(ranges) => {
+            let boundary = Alphanumeric.sample_string(&mut rand::rng(), 24);
+            let (length, body) = stream::multipart(streamer, object, ranges, &ct, &boundary);
+
+            (
+                StatusCode::PARTIAL_CONTENT,
+                format!("multipart/byteranges; boundary={boundary}"),
+                length,
+                (!head).then_some(body),
+            )
+        }
+
+        Ranges::Unsatisfiable => {
+            headers.typed_insert(ContentRange::unsatisfied_bytes(object.size));
+            return Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response());
+        }
+    };
+
+    Ok((
+        status,
+        IMAGE_HEADERS,
+        headers,
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_str(&ct).unwrap()),
+            (header::CONTENT_LENGTH, HeaderValue::from(length)),
+        ],
+        body.map_or_else(Body::empty, Body::from_stream),
+    )
+        .into_response())
+}
 
-        let mime = ct.parse::<mime::Mime>().map_err(|_| {
+/// Returns the value of the header called `name` as a string, if it was sent.
+fn header_value(headers: &HeaderMap, name: &str) -> Result<Option<String>, (StatusCode, Json<Value>)> {
+    headers
+        .get(name)
+        .map(|value| value.to_str().map(ToOwned::to_owned))
+        .transpose()
+        .map_err(|_| {
             (
                 StatusCode::BAD_REQUEST,
                 Json(json!({
-                    "message": "unable to infer field data's contents"
+                    "message": format!("`{name}` header isn't valid utf-8")
                 })),
             )
-        })?;
+        })
+}
+
+/// Response for images that expired but haven't been deleted yet.
+fn gone() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::GONE,
+        Json(json!({
+            "message": "image has expired"
+        })),
+    )
+}
 
-        if mime.type_() != mime::IMAGE {
+/// Resolves the transformation that was requested with the query parameters of `GET /images/{name}`.
+fn resolve_transform(
+    config: &crate::config::Config,
+    image: &str,
+    query: &transform::Query,
+) -> Result<Transform, (StatusCode, Json<Value>)> {
+    let transform = query.transform();
+    if let Some(ref preset) = query.preset {
+        if !transform::is_empty(&transform) {
             return Err((
                 StatusCode::BAD_REQUEST,
                 Json(json!({
-                    "message": "wanted a image from field data's contents, didn't receive one though..."
+                    "message": "presets can't be combined with other transformations"
                 })),
             ));
         }
 
-        return Ok((
-            [
-                (header::CONTENT_TYPE, HeaderValue::from_str(&ct).unwrap()),
-                (header::CONTENT_LENGTH, HeaderValue::from(file.size)),
-            ],
-            file.data,
-        ));
+        return config.transforms.presets.get(preset).cloned().ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(json!({
+                    "message": format!("preset `{preset}` doesn't exist")
+                })),
+            )
+        });
+    }
+
+    if transform::is_empty(&transform) {
+        return Ok(transform);
+    }
+
+    transform::validate(&config.transforms, &transform)
+        .map_err(|message| (StatusCode::BAD_REQUEST, Json(json!({ "message": message }))))?;
+
+    if config.transforms.require_signature {
+        let params = transform::params(&transform);
+        if !is_signed(config, image, &params, query.expires, query.sig.as_deref()) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(json!({
+                    "message": "transformations require a valid signature"
+                })),
+            ));
+        }
     }
 
-    unreachable!()
+    Ok(transform)
 }
 
-#[instrument(name = "ume.upload.image", skip_all)]
-pub async fn upload_image(
-    Extension(storage): Extension<StorageService>,
-    Extension(config): Extension<crate::config::Config>,
-    TypedHeader(UploaderKey(value)): TypedHeader<UploaderKey>,
-    mut multipart: Multipart,
-) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
-    if value != config.uploader_key {
-        return Err((
-            StatusCode::UNAUTHORIZED,
+/// Whether `sig` was minted by `POST /images/{name}/sign` for the image called `image` with
+/// `params`. Signatures that were minted with an expiry are only valid until then.
+fn is_signed(
+    config: &crate::config::Config,
+    image: &str,
+    params: &[(&str, String)],
+    expires: Option<i64>,
+    sig: Option<&str>,
+) -> bool {
+    let Some(sig) = sig else {
+        return false;
+    };
+
+    match expires {
+        Some(expires) => super::signing::verify_until(&config.secret_key, image, params, expires, sig),
+        None => super::signing::verify(&config.secret_key, image, params, sig),
+    }
+}
+
+/// Builds the URL at `path` (relative to the base URL) with `params` signed for the image
+/// called `image`, which is only valid until `expires` if one is given.
+fn signed_url(
+    config: &crate::config::Config,
+    image: &str,
+    path: &str,
+    params: Vec<(&str, String)>,
+    expires: Option<i64>,
+) -> Result<Url, (StatusCode, Json<Value>)> {
+    let mut url = config.base_url.join(path).map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
             Json(json!({
-                "message": "invalid uploader key received"
+                "message": "received an invalid image name"
             })),
-        ));
+        )
+    })?;
+
+    let sig = match expires {
+        Some(expires) => super::signing::sign_until(&config.secret_key, image, &params, expires),
+        None => super::signing::sign(&config.secret_key, image, &params),
+    };
+
+    let mut query = url.query_pairs_mut();
+    query.extend_pairs(params);
+    if let Some(expires) = expires {
+        query.append_pair("expires", &expires.to_string());
     }
 
-    let Some(field) = multipart
-        .next_field()
+    query.append_pair("sig", &sig);
+    drop(query);
+
+    Ok(url)
+}
+
+/// Response for private images that were requested without a valid signed URL.
+fn private_image() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::FORBIDDEN,
+        Json(json!({
+            "message": "image is private, it can only be viewed with a signed url"
+        })),
+    )
+}
+
+/// Response for password-protected images whose information was requested by someone who
+/// isn't an uploader.
+fn protected_image() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(json!({
+            "message": "image is protected by a password"
+        })),
+    )
+}
+
+/// Checks the password that was sent with the request if `record` is password-protected,
+/// see [`Unlocker::unlock`][super::password::Unlocker::unlock].
+async fn unlock(
+    unlocker: &super::password::Unlocker,
+    record: Option<&metadata::Image>,
+    headers: &HeaderMap,
+    address: Option<Extension<ConnectInfo<SocketAddr>>>,
+    password: Option<&str>,
+) -> Result<Option<HeaderValue>, Response> {
+    let Some(record) = record else {
+        return Ok(None);
+    };
+
+    let Some(ref hash) = record.password else {
+        return Ok(None);
+    };
+
+    let address = address.map(|Extension(ConnectInfo(address))| address.ip());
+    unlocker.unlock(&record.name, hash, headers, address, password).await
+}
+
+#[derive(Deserialize)]
+pub struct UnlockForm {
+    password: String,
+}
+
+/// Checks the password that the page from [`prompt`][super::password::prompt] posted, and
+/// sends the browser back to the image (or its thumbnail) with the cookie that remembers
+/// it. The password never ends up in a URL this way.
+#[allow(clippy::too_many_arguments)]
+#[instrument(name = "ume.image.unlock", skip_all, fields(%image))]
+pub async fn unlock_image(
+    Extension(metadata): Extension<metadata::Store>,
+    Extension(config): Extension<crate::config::Config>,
+    Extension(unlocker): Extension<super::password::Unlocker>,
+    address: Option<Extension<ConnectInfo<SocketAddr>>>,
+    Path(image): Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
+    Form(UnlockForm { password }): Form<UnlockForm>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let record = metadata
+        .get(&image)
         .await
         .inspect_err(|e| {
-            error!(error = %e, "failed to get next multipart field");
-            sentry::capture_error(&e);
+            error!(error = %e, %image, "unable to get image metadata");
+            sentry::capture_error::<dyn std::error::Error>(e.as_ref());
         })
-        .map_err(|e| {
+        .map_err(|_| {
             (
-                StatusCode::BAD_REQUEST,
+                StatusCode::INTERNAL_SERVER_ERROR,
                 Json(json!({
-                    "message": format!("multipart error: {}", super::extract::err_to_msg(&e)),
-                    "details": super::extract::expand_details_from_err(&e)
+                    "message": "internal server error, pls try again later"
                 })),
             )
-        })?
-    else {
+        })?;
+
+    let Some(record) = record.filter(metadata::Image::protected) else {
         return Err((
             StatusCode::NOT_FOUND,
             Json(json!({
-                "message": "was expecting a multipart field, but didn't receive anything"
+                "message": "image doesn't exist?"
             })),
         ));
     };
 
-    let bytes = field
-        .bytes()
-        .await
-        .inspect_err(|e| {
-            error!(error = %e, "unable to get bytes from field");
-            sentry::capture_error(&e);
-        })
-        .map_err(|e| {
-            (
-                StatusCode::BAD_REQUEST,
-                Json(json!({
-                    "message": format!("multipart error: {}", super::extract::err_to_msg(&e)),
-                    "details": super::extract::expand_details_from_err(&e)
-                })),
-            )
-        })?;
+    let cookie = match unlock(&unlocker, Some(&record), &headers, address, Some(&password)).await {
+        Ok(cookie) => cookie,
+        Err(response) => return Ok(response),
+    };
+
+    let path = match uri.path().ends_with("/thumbnail") {
+        true => format!("images/{image}/thumbnail"),
+        false => format!("images/{image}"),
+    };
 
-    let content_type = azalia::remi::fs::default_resolver(bytes.as_ref());
-    let mime = content_type.parse::<mime::Mime>().map_err(|_| {
+    let mut location = config.base_url.join(&path).map_err(|_| {
         (
             StatusCode::BAD_REQUEST,
             Json(json!({
-                "message": "unable to infer field data's contents"
+                "message": "received an invalid image name"
             })),
         )
     })?;
 
-    if mime.type_() != mime::IMAGE {
+    // the rest of the query (like a transformation or signature) is kept, but a password
+    // that was given in it isn't
+    let query = url::form_urlencoded::parse(uri.query().unwrap_or_default().as_bytes())
+        .filter(|(key, _)| key != "password")
+        .collect::<Vec<_>>();
+
+    if !query.is_empty() {
+        location.query_pairs_mut().extend_pairs(query);
+    }
+
+    let mut response = Redirect::to(location.as_str()).into_response();
+    if let Some(cookie) = cookie {
+        response.headers_mut().insert(header::SET_COOKIE, cookie);
+    }
+
+    Ok(response)
+}
+
+#[derive(Deserialize)]
+pub struct SignImageQuery {
+    /// how long the signed URL is valid for, like `1h`. URLs of private images are always
+    /// only valid for so long.
+    expires_in: Option<String>,
+}
+
+#[instrument(name = "ume.image.sign", skip_all, fields(%image))]
+pub async fn sign_image(
+    Extension(storage): Extension<StorageService>,
+    Extension(metadata): Extension<metadata::Store>,
+    Extension(config): Extension<crate::config::Config>,
+    Path(image): Path<String>,
+    Query(query): Query<transform::Query>,
+    Query(SignImageQuery { expires_in }): Query<SignImageQuery>,
+    uploader: Uploader,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    uploader.require(Scope::Upload)?;
+
+    let internal_error = |e: eyre::Report| {
+        error!(error = %e, %image, "unable to sign image url");
+        sentry::capture_error::<dyn std::error::Error>(e.as_ref());
+
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "message": "internal server error, pls try again later"
+            })),
+        )
+    };
+
+    let record = metadata.get(&image).await.map_err(internal_error)?;
+    let exists = match record {
+        Some(_) => true,
+        None => storage
+            .exists(format!("./{image}"))
+            .await
+            .map_err(|e| internal_error(e.into()))?,
+    };
+
+    if !exists {
         return Err((
-            StatusCode::BAD_REQUEST,
+            StatusCode::NOT_FOUND,
             Json(json!({
-                "message": "wanted a image from field data's contents, didn't receive one though..."
+                "message": "image doesn't exist?"
             })),
         ));
     }
 
-    let ext = match mime.subtype() {
-        mime::PNG => "png",
-        mime::JPEG => "jpg",
-        mime::GIF => "gif",
-        mime::SVG => "svg",
-        name => {
-            return Err((
-                StatusCode::UNPROCESSABLE_ENTITY,
-                Json(json!({
-                    "message": format!("cannot process {name} as a image")
-                })),
-            ))
-        }
+    // uploaders can only sign URLs of their own images, since signed URLs get around
+    // private images and required signatures
+    let owned = record.as_ref().and_then(|record| record.uploader.as_deref()) == Some(uploader.name());
+    if !owned && !uploader.can(Scope::Admin) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(json!({
+                "message": format!("uploader `{}` can only sign urls of its own images", uploader.name())
+            })),
+        ));
+    }
+
+    let private = record.as_ref().is_some_and(|record| record.private);
+    let ttl = match expires_in {
+        Some(ref expires_in) => Some(
+            charted_core::serde::Duration::from_str(expires_in)
+                .map(std::time::Duration::from)
+                .map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(json!({
+                            "message": format!("`{expires_in}` isn't a valid duration")
+                        })),
+                    )
+                })?,
+        ),
+
+        None => private.then(|| super::signing::default_ttl(&config)),
     };
-    let name = format!("{}.{ext}", Alphanumeric.sample_string(&mut rand::rng(), 6));
 
-    info!(file = %name, "uploading image...");
-    storage
-        .upload(
-            format!("./{name}"),
-            UploadRequest::default()
-                .with_content_type(Some(mime.to_string()))
-                .with_data(bytes),
-        )
-        .await
-        .inspect_err(|e| {
-            error!(error = %e, file = %name, "unable to upload file");
-            sentry::capture_error(&e);
-        })
-        .map(|()| {
+    let max_ttl = std::time::Duration::from(config.max_signed_ttl);
+    if let Some(ttl) = ttl
+        && ttl > max_ttl
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
             Json(json!({
-                "filename": format!("{}images/{}", config.base_url, name)
-            }))
-        })
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({
-                    "message": "received unknown error pls try again later :<"
-                })),
-            )
-        })
+                "message": format!("signed urls can't be valid for longer than {}s", max_ttl.as_secs())
+            })),
+        ));
+    }
+
+    let transform = query.transform();
+    if transform::is_empty(&transform) && ttl.is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "message": "was expecting a transformation to sign"
+            })),
+        ));
+    }
+
+    transform::validate(&config.transforms, &transform)
+        .map_err(|message| (StatusCode::BAD_REQUEST, Json(json!({ "message": message }))))?;
+
+    let expires = ttl.map(|ttl| Utc::now().timestamp().saturating_add_unsigned(ttl.as_secs()));
+    let params = transform::params(&transform);
+
+    // thumbnails can only be signed without a transformation, since they're served as-is
+    let thumbnail_url = match params.is_empty() {
+        true => Some(signed_url(
+            &config,
+            &image,
+            &format!("images/{image}/thumbnail"),
+            Vec::new(),
+            expires,
+        )?),
+
+        false => None,
+    };
+
+    let url = signed_url(&config, &image, &format!("images/{image}"), params, expires)?;
+    Ok(Json(json!({
+        "url": url,
+        "thumbnail_url": thumbnail_url,
+        "expires_at": expires.and_then(|expires| DateTime::<Utc>::from_timestamp(expires, 0))
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct ListImagesQuery {
+    /// how many images to return, up to 100.
+    #[serde(default = "__default_list_limit")]
+    limit: usize,
+
+    /// cursor that was returned from the previous page.
+    cursor: Option<String>,
+
+    /// whether to return the newest (`desc`) or oldest (`asc`) images first.
+    #[serde(default)]
+    order: Order,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    Asc,
+
+    #[default]
+    Desc,
+}
+
+const fn __default_list_limit() -> usize {
+    50
+}
+
+#[instrument(name = "ume.image.list", skip_all)]
+pub async fn list_images(
+    Extension(metadata): Extension<metadata::Store>,
+    Extension(config): Extension<crate::config::Config>,
+    uploader: Uploader,
+    Query(ListImagesQuery { limit, cursor, order }): Query<ListImagesQuery>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    uploader.require(Scope::List)?;
+
+    let cursor = match cursor {
+        Some(cursor) => Some(decode_cursor(&cursor).ok_or_else(|| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "message": "received an invalid cursor"
+                })),
+            )
+        })?),
+
+        None => None,
+    };
+
+    let limit = limit.clamp(1, 100);
+    let mut page = metadata
+        .list(cursor, order == Order::Desc, limit + 1)
+        .await
+        .inspect_err(|e| {
+            error!(error = %e, "unable to list images");
+            sentry::capture_error::<dyn std::error::Error>(e.as_ref());
+        })
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "message": "internal server error, pls try again later"
+                })),
+            )
+        })?;
+
+    let next = (page.len() > limit).then(|| {
+        page.truncate(limit);
+        page.last()
+            .map(|image| encode_cursor(&(image.uploaded_at.timestamp_millis(), image.name.clone())))
+    });
+
+    Ok(Json(json!({
+        "images": page.iter().map(|image| json!({
+            "name": image.name,
+            "url": format!("{}images/{}", config.base_url, image.name),
+            "size": image.size,
+            "content_type": image.content_type,
+            "created_at": image.uploaded_at.to_rfc3339(),
+        })).collect::<Vec<_>>(),
+        "cursor": next.flatten(),
+    })))
+}
+
+fn encode_cursor((created_at, name): &(i64, String)) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(format!("{created_at}:{name}"))
+}
+
+fn decode_cursor(cursor: &str) -> Option<(i64, String)> {
+    let decoded = String::from_utf8(BASE64_URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+    let (created_at, name) = decoded.split_once(':')?;
+
+    Some((created_at.parse().ok()?, name.to_owned()))
+}
+
+/// Image that was received by `POST /images/upload`, which is waiting to be named and stored.
+struct Received {
+    spool: Spool,
+    format: ImageFormat,
+    metadata_stripped: bool,
+    original_filename: Option<String>,
+    caption: Option<String>,
+}
+
+/// Options that apply to every image that is uploaded at once.
+struct UploadOptions {
+    expires_at: Option<DateTime<Utc>>,
+    max_views: Option<u64>,
+    private: bool,
+    password: Option<String>,
+    name: Option<String>,
+}
+
+fn multipart_error(e: multer::Error) -> (StatusCode, Json<Value>) {
+    (
+        super::extract::status_from_err(&e),
+        Json(json!({
+            "message": format!("multipart error: {}", super::extract::err_to_msg(&e)),
+            "details": super::extract::expand_details_from_err(&e)
+        })),
+    )
+}
+
+fn spool_error(e: std::io::Error) -> (StatusCode, Json<Value>) {
+    error!(error = %e, "unable to spool uploaded image");
+    sentry::capture_error(&e);
+
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({
+            "message": "received unknown error pls try again later :<"
+        })),
+    )
+}
+
+async fn next_field(multipart: &mut Multipart) -> Result<Option<multer::Field<'static>>, (StatusCode, Json<Value>)> {
+    multipart
+        .next_field()
+        .await
+        .inspect_err(|e| {
+            error!(error = %e, "failed to get next multipart field");
+            sentry::capture_error(&e);
+        })
+        .map_err(multipart_error)
+}
+
+#[allow(clippy::too_many_arguments)]
+#[instrument(name = "ume.upload.image", skip_all)]
+pub async fn upload_image(
+    Extension(streamer): Extension<Streamer>,
+    Extension(storage): Extension<StorageService>,
+    Extension(metadata): Extension<metadata::Store>,
+    Extension(config): Extension<crate::config::Config>,
+    Extension(pool): Extension<transform::Pool>,
+    uploader: Uploader,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    uploader.require(Scope::Upload)?;
+
+    let mut expires = header_value(&headers, "x-ume-expires")?;
+    let mut max_views = header_value(&headers, "x-ume-max-views")?;
+    let mut private = header_value(&headers, "x-ume-private")?;
+    let mut password = header_value(&headers, super::password::HEADER)?;
+    let mut requested_name = header_value(&headers, "x-ume-filename")?;
+    let mut album = header_value(&headers, "x-ume-album")?;
+    let mut title = header_value(&headers, "x-ume-album-title")?;
+
+    // the `expires`, `max_views`, `private`, `password`, `name`, `album` and `title` form
+    // fields have to come before the images
+    let field = loop {
+        let Some(field) = next_field(&mut multipart).await? else {
+            return Err((
+                StatusCode::NOT_FOUND,
+                Json(json!({
+                    "message": "was expecting a multipart field, but didn't receive anything"
+                })),
+            ));
+        };
+
+        match field.name() {
+            Some("expires") => expires = Some(field.text().await.map_err(multipart_error)?),
+            Some("max_views") => max_views = Some(field.text().await.map_err(multipart_error)?),
+            Some("private") => private = Some(field.text().await.map_err(multipart_error)?),
+            Some("password") => password = Some(field.text().await.map_err(multipart_error)?),
+            Some("name") => requested_name = Some(field.text().await.map_err(multipart_error)?),
+            Some("album") => album = Some(field.text().await.map_err(multipart_error)?),
+            Some("title") => title = Some(field.text().await.map_err(multipart_error)?),
+            _ => break field,
+        }
+    };
+
+    // names are first come, first served, so only uploaders that are trusted to not
+    // squat on them can choose one
+    let requested_name = requested_name.filter(|name| !name.trim().is_empty());
+    if requested_name.is_some() {
+        uploader.require(Scope::Name)?;
+    }
+
+    let expires_at = super::expiry::resolve(&config.uploads, expires.as_deref())
+        .map_err(|message| (StatusCode::BAD_REQUEST, Json(json!({ "message": message }))))?;
+
+    let max_views = max_views
+        .as_deref()
+        .map(super::views::parse_max_views)
+        .transpose()
+        .map_err(|message| (StatusCode::BAD_REQUEST, Json(json!({ "message": message }))))?;
+
+    let private = private.is_some_and(|private| azalia::TRUTHY_REGEX.is_match(private.trim()));
+    let album = album.is_some_and(|album| azalia::TRUTHY_REGEX.is_match(album.trim()));
+    let title = title
+        .map(|title| title.trim().to_owned())
+        .filter(|title| !title.is_empty());
+
+    // album pages can be seen by anyone, as many times as they want
+    if album && (private || max_views.is_some() || password.as_ref().is_some_and(|p| !p.is_empty())) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "message": "albums can't have private, password-protected or view-limited images"
+            })),
+        ));
+    }
+
+    let password = match password.filter(|password| !password.is_empty()) {
+        Some(password) => Some(hash_password(password).await?),
+        None => None,
+    };
+
+    // every other field is an image, which can have a `caption` field before it
+    let mut received = Vec::new();
+    let mut caption = None;
+    let mut next = Some(field);
+    while let Some(field) = next {
+        if field.name() == Some("caption") {
+            caption = Some(field.text().await.map_err(multipart_error)?);
+        } else {
+            if requested_name.is_some() && !received.is_empty() {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "message": "a name can only be chosen when uploading a single image"
+                    })),
+                ));
+            }
+
+            let caption = caption.take().filter(|caption| !caption.trim().is_empty());
+            received.push(receive_field(&config, &pool, field, caption).await?);
+        }
+
+        next = next_field(&mut multipart).await?;
+    }
+
+    if received.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "message": "was expecting an image, but only received a caption"
+            })),
+        ));
+    }
+
+    let options = UploadOptions {
+        expires_at,
+        max_views,
+        private,
+        password,
+        name: requested_name,
+    };
+
+    // a single image is responded with as-is, which is what ShareX and friends expect
+    let single = received.len() == 1 && !album;
+    let mut images = Vec::with_capacity(received.len());
+    let mut entries = Vec::with_capacity(received.len());
+    let mut stored = Vec::with_capacity(received.len());
+    for received in received {
+        let caption = received.caption.clone();
+        let (name, image, record) = match store_upload(
+            &streamer, &storage, &metadata, &config, &pool, &uploader, &options, received,
+        )
+        .await
+        {
+            Ok(uploaded) => uploaded,
+            Err(e) => {
+                roll_back(&storage, &metadata, &config, &stored).await;
+                return Err(e);
+            }
+        };
+
+        entries.push(metadata::AlbumImage { name, caption });
+        images.push(image);
+        stored.extend(record);
+    }
+
+    if single && let Some(image) = images.pop() {
+        return Ok(Json(image));
+    }
+
+    let album_url = match album {
+        true => {
+            let album = match create_album(&config, &metadata, &uploader, title, entries).await {
+                Ok(album) => album,
+                Err(e) => {
+                    roll_back(&storage, &metadata, &config, &stored).await;
+                    return Err(album_error(e));
+                }
+            };
+
+            info!(album = %album.id, uploader = uploader.name(), "created album");
+            Some(format!("{}albums/{}", config.base_url, album.id))
+        }
+
+        false => None,
+    };
+
+    Ok(Json(json!({
+        "images": images,
+        "album_url": album_url
+    })))
+}
+
+async fn create_album(
+    config: &crate::config::Config,
+    metadata: &metadata::Store,
+    uploader: &Uploader,
+    title: Option<String>,
+    images: Vec<metadata::AlbumImage>,
+) -> eyre::Result<metadata::Album> {
+    let mut album = metadata::Album {
+        id: String::new(),
+        title,
+        uploader: Some(uploader.name().to_owned()),
+        created_at: Utc::now(),
+        images,
+    };
+
+    album.id = super::naming::album(&config.naming, metadata, &album).await?;
+    Ok(album)
+}
+
+/// Deletes the images that were stored by a request that uploaded more than one image,
+/// after one of them (or their album) couldn't be, so that the request either stores
+/// every image or none of them. Their deletion tokens were never given out.
+async fn roll_back(
+    storage: &StorageService,
+    metadata: &metadata::Store,
+    config: &crate::config::Config,
+    stored: &[metadata::Image],
+) {
+    for image in stored {
+        warn!(file = %image.name, "deleting image that was uploaded along with one that couldn't be");
+        if let Err(e) = super::deletion::delete(storage, metadata, &config.uploads.thumbnails, image).await {
+            error!(error = %e, file = %image.name, "unable to delete image");
+            sentry::capture_error::<dyn std::error::Error>(e.as_ref());
+        }
+    }
+}
+
+/// Hashes the password that an image is protected with, away from the async runtime.
+async fn hash_password(password: String) -> Result<String, (StatusCode, Json<Value>)> {
+    tokio::task::spawn_blocking(move || crate::config::hash_key(&password))
+        .await
+        .map_err(eyre::Report::from)
+        .flatten()
+        .map_err(|e| {
+            error!(error = %e, "unable to hash image password");
+            sentry::capture_error::<dyn std::error::Error>(e.as_ref());
+
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "message": "received unknown error pls try again later :<"
+                })),
+            )
+        })
+}
+
+fn album_error(e: eyre::Report) -> (StatusCode, Json<Value>) {
+    error!(error = %e, "unable to create album");
+    sentry::capture_error::<dyn std::error::Error>(e.as_ref());
+
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({
+            "message": "received unknown error pls try again later :<"
+        })),
+    )
+}
+
+fn body_error(e: axum::Error) -> (StatusCode, Json<Value>) {
+    match super::extract::exceeded_body_limit(&e) {
+        true => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(json!({
+                "message": "request body is larger than the server allows"
+            })),
+        ),
+
+        false => {
+            error!(error = %e, "unable to read request body");
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "message": "reading request body had failed"
+                })),
+            )
+        }
+    }
+}
+
+/// Reads the options of an upload from the headers that `POST /images/upload` also
+/// takes, for uploads that can't have form fields. `name` is the name that was asked for
+/// elsewhere in the request, which takes priority over the `X-Ume-Filename` header.
+async fn header_options(
+    config: &crate::config::Config,
+    uploader: &Uploader,
+    headers: &HeaderMap,
+    name: Option<String>,
+) -> Result<UploadOptions, (StatusCode, Json<Value>)> {
+    let requested_name = match name {
+        name @ Some(_) => name,
+        None => header_value(headers, "x-ume-filename")?,
+    }
+    .filter(|name| !name.trim().is_empty());
+
+    if requested_name.is_some() {
+        uploader.require(Scope::Name)?;
+    }
+
+    let expires = header_value(headers, "x-ume-expires")?;
+    let max_views = header_value(headers, "x-ume-max-views")?;
+    let private = header_value(headers, "x-ume-private")?;
+    let password = header_value(headers, super::password::HEADER)?;
+
+    let expires_at = super::expiry::resolve(&config.uploads, expires.as_deref())
+        .map_err(|message| (StatusCode::BAD_REQUEST, Json(json!({ "message": message }))))?;
+
+    let max_views = max_views
+        .as_deref()
+        .map(super::views::parse_max_views)
+        .transpose()
+        .map_err(|message| (StatusCode::BAD_REQUEST, Json(json!({ "message": message }))))?;
+
+    let private = private.is_some_and(|private| azalia::TRUTHY_REGEX.is_match(private.trim()));
+    let password = match password.filter(|password| !password.is_empty()) {
+        Some(password) => Some(hash_password(password).await?),
+        None => None,
+    };
+
+    Ok(UploadOptions {
+        expires_at,
+        max_views,
+        private,
+        password,
+        name: requested_name,
+    })
+}
+
+/// Uploads the image that is the request body, like `curl -T shot.png`. The image is given
+/// the name in `PUT /images/{name}` if the uploader can choose names, otherwise that's only
+/// kept as its original filename. The options that `POST /images/upload` takes as form
+/// fields are only read from its headers.
+///
+/// The URL of the image is responded with in plain text if that's what the uploader
+/// accepts, otherwise this responds like `POST /images/upload` does.
+#[allow(clippy::too_many_arguments)]
+#[instrument(name = "ume.upload.raw", skip_all)]
+pub async fn put_image(
+    Extension(streamer): Extension<Streamer>,
+    Extension(storage): Extension<StorageService>,
+    Extension(metadata): Extension<metadata::Store>,
+    Extension(config): Extension<crate::config::Config>,
+    Extension(pool): Extension<transform::Pool>,
+    uploader: Uploader,
+    name: Option<Path<String>>,
+    headers: HeaderMap,
+    request: axum::extract::Request,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    uploader.require(Scope::Upload)?;
+
+    // `curl -T shot.png` always puts to `/images/shot.png`, which is only a name that the
+    // uploader asked for if they're allowed to ask for one
+    let original_filename = name.map(|Path(name)| name);
+    let requested_name = original_filename.clone().filter(|_| uploader.can(Scope::Name));
+    let options = header_options(&config, &uploader, &headers, requested_name).await?;
+
+    // the body limit only applies to the body when it's read through something that
+    // knows about it, like the multipart extractor
+    let chunks = request
+        .with_limited_body()
+        .into_body()
+        .into_data_stream()
+        .map_err(body_error);
+
+    let received = receive(&config, &pool, chunks, original_filename, None).await?;
+    let (_, image, _) = store_upload(
+        &streamer, &storage, &metadata, &config, &pool, &uploader, &options, received,
+    )
+    .await?;
+
+    let wants_text = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/plain"));
+
+    match (wants_text, image["filename"].as_str()) {
+        (true, Some(url)) => Ok(format!("{url}\n").into_response()),
+        _ => Ok(Json(image).into_response()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FetchImageRequest {
+    /// URL of the image to download.
+    url: Url,
+}
+
+/// Downloads the image at the given URL and uploads it like `POST /images/upload` does.
+/// The options of the upload are read from the same headers that `PUT /images` reads.
+#[allow(clippy::too_many_arguments)]
+#[instrument(name = "ume.upload.fetch", skip_all)]
+pub async fn fetch_image(
+    Extension(streamer): Extension<Streamer>,
+    Extension(storage): Extension<StorageService>,
+    Extension(metadata): Extension<metadata::Store>,
+    Extension(config): Extension<crate::config::Config>,
+    Extension(pool): Extension<transform::Pool>,
+    Extension(fetcher): Extension<Fetcher>,
+    uploader: Uploader,
+    headers: HeaderMap,
+    body: Result<Json<FetchImageRequest>, JsonRejection>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    uploader.require(Scope::Upload)?;
+
+    let Json(body) = body.map_err(|rejection| {
+        (
+            rejection.status(),
+            Json(json!({
+                "message": rejection.body_text()
+            })),
+        )
+    })?;
+
+    let options = header_options(&config, &uploader, &headers, None).await?;
+
+    // the download counts towards the uploader's limit until the image is stored
+    let _permit = fetcher.permit(uploader.name()).map_err(fetch_error)?;
+    info!(url = %body.url, uploader = uploader.name(), "downloading image...");
+    let download = fetcher.fetch(body.url).await.map_err(fetch_error)?;
+    let filename = download.filename();
+
+    let received = receive(&config, &pool, download.chunks().map_err(fetch_error), filename, None).await?;
+    let (_, image, _) = store_upload(
+        &streamer, &storage, &metadata, &config, &pool, &uploader, &options, received,
+    )
+    .await?;
+
+    Ok(Json(image))
+}
+
+fn fetch_error(e: fetch::Error) -> (StatusCode, Json<Value>) {
+    let status = e.status();
+    match e {
+        fetch::Error::Request(ref err) => warn!(error = %err, "unable to download image"),
+        ref e => debug!(error = %e, "refused to download image"),
+    }
+
+    (
+        status,
+        Json(json!({
+            "message": e.to_string()
+        })),
+    )
+}
+
+/// Receives the image from the multipart `field`.
+async fn receive_field(
+    config: &crate::config::Config,
+    pool: &transform::Pool,
+    field: multer::Field<'static>,
+    caption: Option<String>,
+) -> Result<Received, (StatusCode, Json<Value>)> {
+    let original_filename = field.file_name().map(ToOwned::to_owned);
+    let chunks = field
+        .inspect_err(|e| {
+            error!(error = %e, "unable to get bytes from field");
+            sentry::capture_error(&e);
+        })
+        .map_err(multipart_error);
+
+    receive(config, pool, chunks, original_filename, caption).await
+}
+
+/// Receives the image in `chunks`, which is written to a temporary file and has its
+/// metadata stripped.
+async fn receive(
+    config: &crate::config::Config,
+    pool: &transform::Pool,
+    chunks: impl Stream<Item = Result<Bytes, (StatusCode, Json<Value>)>>,
+    original_filename: Option<String>,
+    caption: Option<String>,
+) -> Result<Received, (StatusCode, Json<Value>)> {
+    let mut chunks = std::pin::pin!(chunks);
+
+    // only the start of the image is needed to know what it is, so anything that isn't
+    // an image is turned away before the rest of it is received
+    let mut head = Vec::with_capacity(ImageFormat::SNIFF_LENGTH);
+    while head.len() < ImageFormat::SNIFF_LENGTH {
+        match chunks.try_next().await? {
+            Some(chunk) => head.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+
+    let Some(format) = ImageFormat::sniff(&head) else {
+        let content_type = azalia::remi::fs::default_resolver(&head);
+        return Err(match content_type.strip_prefix("image/") {
+            Some(name) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({
+                    "message": format!("cannot process {name} as a image")
+                })),
+            ),
+
+            None => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "message": "wanted a image from field data's contents, didn't receive one though..."
+                })),
+            ),
+        });
+    };
+
+    if !config.uploads.formats.contains(&format) {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({
+                "message": format!("{format} images can't be uploaded to this server")
+            })),
+        ));
+    }
+
+    // the rest of the image is written to a temporary file rather than kept in memory
+    let spool = Spool::new().map_err(spool_error)?;
+    let mut file = tokio::fs::File::create(spool.path()).await.map_err(spool_error)?;
+    file.write_all(&head).await.map_err(spool_error)?;
+
+    // SVG images are processed in memory, so they're limited separately
+    let limit = match format {
+        ImageFormat::Svg => config.uploads.max_svg_size,
+        _ => usize::MAX,
+    };
+
+    let mut size = head.len();
+    drop(head);
+
+    loop {
+        if size > limit {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(json!({
+                    "message": format!("svg images can't be larger than {limit} bytes")
+                })),
+            ));
+        }
+
+        let Some(chunk) = chunks.try_next().await? else {
+            break;
+        };
+
+        size += chunk.len();
+        file.write_all(&chunk).await.map_err(spool_error)?;
+    }
+
+    file.flush().await.map_err(spool_error)?;
+    drop(file);
+
+    // SVG images are small enough to be processed in memory, since they're limited above
+    let (format, spool) = match format {
+        ImageFormat::Svg => {
+            let data = spool.bytes().await.map_err(spool_error)?;
+            let (format, data) = match config.uploads.svg {
+                Svg::Sanitize => (format, svg::sanitize(&data)),
+                Svg::Rasterize => {
+                    let max_dimension = config.transforms.max_dimension;
+                    let data = pool
+                        .run(move || svg::sanitize(&data).and_then(|data| svg::rasterize(&data, max_dimension)))
+                        .await;
+
+                    (ImageFormat::Png, data)
+                }
+            };
+
+            let data = data
+                .inspect_err(|e| {
+                    warn!(error = %e, "unable to process svg image");
+                })
+                .map_err(|e| {
+                    (
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        Json(json!({
+                            "message": format!("unable to process svg image: {e}")
+                        })),
+                    )
+                })?;
+
+            (format, Spool::from_bytes(&data).await.map_err(spool_error)?)
+        }
+
+        format => (format, spool),
+    };
+
+    let (spool, metadata_stripped) = if config.uploads.strip_metadata {
+        let sanitized = super::sanitize::sanitize(pool, format, spool)
+            .await
+            .map_err(spool_error)?;
+
+        (sanitized.spool, sanitized.stripped)
+    } else {
+        (spool, false)
+    };
+
+    Ok(Received {
+        spool,
+        format,
+        metadata_stripped,
+        original_filename,
+        caption,
+    })
+}
+
+/// Names and stores an image that was received, and returns its name along with what
+/// the uploader is responded with and its record, unless an existing image was reused.
+#[allow(clippy::too_many_arguments)]
+async fn store_upload(
+    streamer: &Streamer,
+    storage: &StorageService,
+    metadata: &metadata::Store,
+    config: &crate::config::Config,
+    pool: &transform::Pool,
+    uploader: &Uploader,
+    options: &UploadOptions,
+    received: Received,
+) -> Result<(String, Value, Option<metadata::Image>), (StatusCode, Json<Value>)> {
+    let Received {
+        spool,
+        format,
+        metadata_stripped,
+        original_filename,
+        ..
+    } = received;
+
+    let (size, sha256) = spool.digest().await.map_err(spool_error)?;
+    let naming_error = |e: eyre::Report| {
+        error!(error = %e, "unable to name uploaded image");
+        sentry::capture_error::<dyn std::error::Error>(e.as_ref());
+
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "message": "received unknown error pls try again later :<"
+            })),
+        )
+    };
+
+    let vanity = options
+        .name
+        .as_deref()
+        .map(|requested| super::naming::vanity(requested, format))
+        .transpose()
+        .map_err(|message| (StatusCode::BAD_REQUEST, Json(json!({ "message": message }))))?;
+
+    let (width, height) = spool.dimensions().await.unzip();
+    let mut record = metadata::Image {
+        name: String::new(),
+        uploader: Some(uploader.name().to_owned()),
+        original_filename,
+        uploaded_at: Utc::now(),
+        size,
+        width,
+        height,
+        sha256,
+        content_type: format.content_type().to_owned(),
+        alias_of: None,
+        expires_at: options.expires_at,
+        max_views: options.max_views,
+        views: 0,
+        preview_views: 0,
+        private: options.private,
+        password: options.password.clone(),
+    };
+
+    // images are only reused for whoever uploaded them, otherwise anyone could find out
+    // if an image was uploaded before and what it's called.
+    let deduplicate = config.uploads.deduplicate;
+    let existing = match deduplicate {
+        Deduplicate::Off => None,
+        Deduplicate::Reuse | Deduplicate::Alias => metadata
+            .find_by_hash(
+                &record.sha256,
+                (deduplicate == Deduplicate::Reuse).then(|| uploader.name()),
+            )
+            .await
+            .inspect_err(|e| {
+                error!(error = %e, "unable to look up identical images");
+                sentry::capture_error::<dyn std::error::Error>(e.as_ref());
+            })
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "message": "received unknown error pls try again later :<"
+                    })),
+                )
+            })?,
+    };
+
+    // images that expire or have a view limit are never shared with other uploads, and
+    // uploads that do get their own image. The same goes for private and password-protected
+    // images, since whether an image can be seen would otherwise depend on another one.
+    let existing = existing.filter(|existing| {
+        [existing, &record]
+            .iter()
+            .all(|image| !image.ephemeral() && !image.private && !image.protected())
+    });
+
+    match (deduplicate, existing) {
+        // uploads that chose their own name want to be found under it, so they aren't
+        // handed another image
+        (Deduplicate::Reuse, Some(existing)) if vanity.is_none() => {
+            info!(file = %existing.name, uploader = uploader.name(), "image was uploaded before, reusing it");
+
+            // the deletion token of the existing image is only known to whoever uploaded it
+            let response = json!({
+                "filename": format!("{}images/{}", config.base_url, existing.name),
+                "thumbnail_url": format!("{}images/{}/thumbnail", config.base_url, existing.name),
+                "deletion_url": null,
+                "metadata_stripped": metadata_stripped,
+                "expires_at": existing.expires_at,
+                "max_views": existing.max_views,
+                "private": existing.private,
+                "password_protected": existing.protected(),
+                "deduplicated": true
+            });
+
+            return Ok((existing.name, response, None));
+        }
+
+        (Deduplicate::Alias, Some(existing)) => record.alias_of = Some(existing.name),
+        _ => {}
+    }
+
+    // the record is kept as soon as the image is named, so that no other upload can take
+    // the same name while this one is being uploaded
+    let name = match vanity {
+        Some(name) => {
+            record.name = name.clone();
+            if !super::naming::reserve(storage, metadata, &record)
+                .await
+                .map_err(naming_error)?
+            {
+                return Err((
+                    StatusCode::CONFLICT,
+                    Json(json!({
+                        "message": format!("an image called `{name}` already exists")
+                    })),
+                ));
+            }
+
+            name
+        }
+
+        None => super::naming::generate(&config.naming, storage, metadata, &record, format.extension())
+            .await
+            .map_err(naming_error)?,
+    };
+
+    record.name = name.clone();
+    match record.alias_of {
+        Some(ref existing) => info!(
+            file = %name,
+            alias_of = %existing,
+            uploader = uploader.name(),
+            "image was uploaded before, aliasing it"
+        ),
+
+        None => {
+            info!(file = %name, uploader = uploader.name(), "uploading image...");
+            if let Err(e) = streamer
+                .upload(&format!("./{name}"), format.content_type(), spool.path())
+                .await
+            {
+                error!(error = %e, file = %name, "unable to upload file");
+                sentry::capture_error::<dyn std::error::Error>(e.as_ref());
+
+                // the name is given back, since there's nothing under it
+                if let Err(e) = metadata.delete(&name).await {
+                    warn!(error = %e, file = %name, "unable to remove record of image that couldn't be uploaded");
+                }
+
+                return Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "message": "received unknown error pls try again later :<"
+                    })),
+                ));
+            }
+
+            // thumbnails are generated in the background, the thumbnail route serves the
+            // original image until they're ready. Images with a view limit don't get any,
+            // since they would let the image be seen without using up a view, and neither
+            // do images that are too large to be decoded.
+            if record.max_views.is_none() && record.size <= config.uploads.max_thumbnail_source_size {
+                let (storage, pool, record, sizes) = (
+                    storage.clone(),
+                    pool.clone(),
+                    record.clone(),
+                    config.uploads.thumbnails.clone(),
+                );
+
+                tokio::spawn(
+                    async move {
+                        // the image is read from the temporary file, which is removed afterwards
+                        let generated =
+                            super::thumbnail::generate(&storage, &pool, &record, spool.path(), &sizes).await;
+                        if let Err(e) = generated {
+                            warn!(error = %e, file = %record.name, "unable to generate thumbnails");
+                        }
+                    }
+                    .in_current_span(),
+                );
+            }
+        }
+    }
+
+    let token = super::deletion::generate();
+    super::deletion::persist(storage, &name, &token)
+        .await
+        .inspect_err(|e| {
+            error!(error = %e, file = %name, "unable to persist deletion token");
+            sentry::capture_error(&e);
+        })
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "message": "received unknown error pls try again later :<"
+                })),
+            )
+        })?;
+
+    // private images can only be viewed with a signed URL, so the uploader gets one that
+    // is valid for a while
+    let (filename, thumbnail_url) = match record.private {
+        true => {
+            let expires = Utc::now()
+                .timestamp()
+                .saturating_add_unsigned(super::signing::default_ttl(config).as_secs());
+
+            let filename = signed_url(config, &name, &format!("images/{name}"), Vec::new(), Some(expires))?;
+            let thumbnail_url = signed_url(
+                config,
+                &name,
+                &format!("images/{name}/thumbnail"),
+                Vec::new(),
+                Some(expires),
+            )?;
+
+            (filename.to_string(), thumbnail_url.to_string())
+        }
+
+        false => (
+            format!("{}images/{}", config.base_url, name),
+            format!("{}images/{}/thumbnail", config.base_url, name),
+        ),
+    };
+
+    let response = json!({
+        "filename": filename,
+        "thumbnail_url": record.max_views.is_none().then_some(thumbnail_url),
+        "deletion_url": format!("{}images/{}/delete?token={}", config.base_url, name, token),
+        "metadata_stripped": metadata_stripped,
+        "expires_at": record.expires_at,
+        "max_views": record.max_views,
+        "private": record.private,
+        "password_protected": record.protected(),
+        "deduplicated": record.alias_of.is_some()
+    });
+
+    Ok((name, response, Some(record)))
+}
+
+#[instrument(name = "ume.album.get", skip_all, fields(%id))]
+pub async fn get_album(
+    Extension(storage): Extension<StorageService>,
+    Extension(metadata): Extension<metadata::Store>,
+    Extension(config): Extension<crate::config::Config>,
+    Path(id): Path<String>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    // album ids are only ever generated from the alphabet, and they end up in storage
+    // paths with the sidecar metadata store
+    if id.is_empty() || !id.chars().all(|ch| config.naming.alphabet.contains(ch)) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "message": "album doesn't exist?"
+            })),
+        ));
+    }
+
+    let internal_error = |e: eyre::Report| {
+        error!(error = %e, album = %id, "unable to get album");
+        sentry::capture_error::<dyn std::error::Error>(e.as_ref());
+
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "message": "internal server error, pls try again later"
+            })),
+        )
+    };
+
+    let Some(album) = metadata.get_album(&id).await.map_err(internal_error)? else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "message": "album doesn't exist?"
+            })),
+        ));
+    };
+
+    // images that were deleted or have expired since are left out, and so are images
+    // that can't be seen by everyone
+    let mut images = Vec::with_capacity(album.images.len());
+    for image in &album.images {
+        let shown = match metadata.get(&image.name).await.map_err(internal_error)? {
+            Some(record) => !record.expired() && !record.private && !record.protected() && record.max_views.is_none(),
+            None => storage
+                .exists(format!("./{}", image.name))
+                .await
+                .map_err(|e| internal_error(e.into()))?,
+        };
+
+        if shown {
+            images.push(image);
+        }
+    }
+
+    Ok(super::album::render(&config, &album, &images))
+}
+
+#[derive(Deserialize)]
+pub struct DeleteImageQuery {
+    /// deletion token that was given when the image was uploaded.
+    token: Option<String>,
+}
+
+#[instrument(name = "ume.image.delete", skip_all, fields(%image))]
+pub async fn delete_image(
+    Extension(storage): Extension<StorageService>,
+    Extension(metadata): Extension<metadata::Store>,
+    Extension(config): Extension<crate::config::Config>,
+    Path(image): Path<String>,
+    Query(DeleteImageQuery { token }): Query<DeleteImageQuery>,
+    uploader: Option<Uploader>,
+) -> Result<StatusCode, Response> {
+    if image.contains("..") || image.starts_with('.') {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "message": "route not found"
+            })),
+        )
+            .into_response());
+    }
+
+    let internal_error = |e: eyre::Report| {
+        error!(error = %e, %image, "unable to delete image");
+        sentry::capture_error::<dyn std::error::Error>(e.as_ref());
+
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "message": "internal server error, pls try again later"
+            })),
+        )
+            .into_response()
+    };
+
+    let record = metadata.get(&image).await.map_err(internal_error)?;
+    let exists = match record {
+        Some(_) => true,
+        None => storage
+            .exists(format!("./{image}"))
+            .await
+            .map_err(|e| internal_error(e.into()))?,
+    };
+
+    if !exists {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "message": "image doesn't exist?"
+            })),
+        )
+            .into_response());
+    }
+
+    let authorized = match (uploader, token) {
+        (Some(uploader), _) if uploader.can(Scope::Delete) => true,
+        (_, Some(token)) => super::deletion::verify(&storage, &image, &token)
+            .await
+            .map_err(|e| internal_error(e.into()))?,
+
+        _ => false,
+    };
+
+    if !authorized {
+        return Err(super::auth::unauthorized(
+            "invalid deletion token or uploader key received",
+        ));
+    }
+
+    info!("deleting image...");
+    match record {
+        Some(ref record) => super::deletion::delete(&storage, &metadata, &config.uploads.thumbnails, record)
+            .await
+            .map_err(internal_error)?,
+
+        None => {
+            storage
+                .delete(format!("./{image}"))
+                .await
+                .map_err(|e| internal_error(e.into()))?;
+
+            super::deletion::forget(&storage, &image)
+                .await
+                .map_err(|e| internal_error(e.into()))?;
+        }
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[instrument(name = "ume.image.info", skip_all, fields(%image))]
+pub async fn get_image_info(
+    Extension(metadata): Extension<metadata::Store>,
+    Path(image): Path<String>,
+    uploader: Option<Uploader>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let Some(mut info) = metadata
+        .get(&image)
+        .await
+        .inspect_err(|e| {
+            error!(error = %e, %image, "unable to get image metadata");
+            sentry::capture_error::<dyn std::error::Error>(e.as_ref());
+        })
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "message": "internal server error, pls try again later"
+                })),
+            )
+        })?
+    else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "message": "image doesn't exist?"
+            })),
+        ));
+    };
+
+    if info.expired() {
+        return Err(gone());
+    }
+
+    if info.private && uploader.is_none() {
+        return Err(private_image());
+    }
+
+    if info.protected() && uploader.is_none() {
+        return Err(protected_image());
+    }
+
+    // who uploaded the image and what it was called on their machine is only
+    // shown to other uploaders
+    if uploader.is_none() {
+        info.uploader = None;
+        info.original_filename = None;
+    }
+
+    // the password's hash is never shown to anyone, only whether the image has one
+    let protected = info.password.take().is_some();
+    let mut info = json!(info);
+    info["password_protected"] = json!(protected);
+
+    Ok(Json(info))
+}
+
+#[derive(Deserialize)]
+pub struct ThumbnailQuery {
+    /// size of the thumbnail, which has to be one of the configured sizes.
+    size: Option<u32>,
+
+    /// when the signed URL of a private image's thumbnail expires.
+    expires: Option<i64>,
+
+    /// signature of a private image's thumbnail.
+    sig: Option<String>,
+
+    /// password of a password-protected image.
+    password: Option<String>,
+}
+
+#[allow(clippy::too_many_arguments)]
+#[instrument(name = "ume.image.thumbnail", skip_all, fields(%image))]
+pub async fn get_thumbnail(
+    Extension(storage): Extension<StorageService>,
+    Extension(metadata): Extension<metadata::Store>,
+    Extension(config): Extension<crate::config::Config>,
+    Extension(unlocker): Extension<super::password::Unlocker>,
+    address: Option<Extension<ConnectInfo<SocketAddr>>>,
+    Path(image): Path<String>,
+    headers: HeaderMap,
+    conditions: Conditions,
+    Query(ThumbnailQuery {
+        size,
+        expires,
+        sig,
+        password,
+    }): Query<ThumbnailQuery>,
+    RawQuery(raw_query): RawQuery,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    if image.contains("..") || image.starts_with('.') {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "message": "route not found"
+            })),
+        ));
+    }
+
+    let sizes = &config.uploads.thumbnails;
+    if size.is_some_and(|size| !sizes.contains(&size)) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "message": "thumbnail size doesn't exist"
+            })),
+        ));
+    }
+
+    let internal_error = |e: eyre::Report| {
+        error!(error = %e, %image, "unable to get thumbnail");
+        sentry::capture_error::<dyn std::error::Error>(e.as_ref());
+
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "message": "internal server error, pls try again later"
+            })),
+        )
+    };
+
+    let record = metadata.get(&image).await.map_err(internal_error)?;
+    if record.as_ref().is_some_and(metadata::Image::expired) {
+        return Err(gone());
+    }
+
+    let private = record.as_ref().is_some_and(|record| record.private);
+    if private && (expires.is_none() || !is_signed(&config, &image, &[], expires, sig.as_deref())) {
+        return Err(private_image());
+    }
+
+    let cookie = match unlock(&unlocker, record.as_ref(), &headers, address, password.as_deref()).await {
+        Ok(cookie) => cookie,
+        Err(response) => return Ok(response),
+    };
+
+    // private and password-protected images can only be kept by whoever had the signed
+    // URL or password, like their originals
+    let cache_control = match private || record.as_ref().is_some_and(metadata::Image::protected) {
+        true => "private",
+        false => config.server.cache_control.as_str(),
+    };
+
+    // images with a view limit don't have thumbnails, they have to be viewed as a whole
+    let thumbnail = match (record, size.or(sizes.first().copied())) {
+        (Some(record), Some(size)) if record.max_views.is_none() => storage
+            .open(super::thumbnail::path(&record.sha256, size))
+            .await
+            .map_err(|e| internal_error(e.into()))?
+            .map(|data| {
+                let validators = Validators::new(&record.sha256, &[("thumbnail", size.to_string())], None);
+                (data, validators)
+            }),
+
+        _ => None,
+    };
+
+    // images that are too small, couldn't be decoded or are still being processed don't
+    // have a thumbnail, so the original is used instead
+    let Some((data, validators)) = thumbnail else {
+        // the signature of a private image's thumbnail is also valid for the original, and
+        // so is the cookie that the password of a password-protected one set. The password
+        // itself is never put in the URL.
+        let mut location = config.base_url.join(&format!("images/{image}")).map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "message": "received an invalid image name"
+                })),
+            )
+        })?;
+
+        if expires.is_some() && sig.is_some() {
+            location.query_pairs_mut().extend_pairs(
+                url::form_urlencoded::parse(raw_query.unwrap_or_default().as_bytes())
+                    .filter(|(key, _)| key != "size" && key != "password"),
+            );
+        }
+
+        let mut response = Redirect::temporary(location.as_str()).into_response();
+        if let Some(cookie) = cookie {
+            response.headers_mut().insert(header::SET_COOKIE, cookie);
+        }
+
+        return Ok(response);
+    };
+
+    let mut headers = validators.headers(cache_control);
+    if let Some(cookie) = cookie {
+        headers.insert(header::SET_COOKIE, cookie);
+    }
+
+    if validators.not_modified(&conditions) {
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+    }
+
+    Ok((
+        IMAGE_HEADERS,
+        headers,
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str(&azalia::remi::fs::default_resolver(&data)).unwrap(),
+        )],
+        data,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{backfill, create_app, metadata, stream::Streamer};
+    use crate::config::{self, Config};
+    use azalia::remi::{
+        StorageService,
+        core::{StorageService as _, UploadRequest},
+        fs,
+    };
+    use chrono::{TimeDelta, Utc};
+    use image::RgbImage;
+    use reqwest::StatusCode;
+    use std::{io::Cursor, net::SocketAddr, path::PathBuf};
+    use tempfile::TempDir;
+
+    /// **ume** server that is listening on a random port, which keeps images in a
+    /// temporary directory and their metadata in memory.
+    struct Server {
+        url: String,
+        storage: StorageService,
+        metadata: metadata::Store,
+        _dir: TempDir,
+    }
+
+    impl Server {
+        async fn start(config: &str) -> Server {
+            let dir = TempDir::new().unwrap();
+            let mut config: Config = toml::from_str(config).unwrap();
+            config.storage = config::storage::Config::Filesystem(fs::StorageConfig::new(dir.path()));
+            config.metadata = config::metadata::Config::Sqlite(config::metadata::Sqlite {
+                path: Some(PathBuf::from(":memory:")),
+            });
+
+            let (storage, streamer) = Streamer::new(&config.storage).unwrap();
+            storage.init().await.unwrap();
+
+            let metadata = metadata::Store::new(&config.metadata, &storage).unwrap();
+            let router = create_app(storage.clone(), streamer, metadata.clone(), &config).unwrap();
+
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let service = router.into_make_service_with_connect_info::<SocketAddr>();
+            tokio::spawn(async move { axum::serve(listener, service).await });
+
+            Server {
+                url,
+                storage,
+                metadata,
+                _dir: dir,
+            }
+        }
+
+        /// Stores a PNG image as `record.name` along with `record`.
+        async fn store(&self, record: &metadata::Image) {
+            self.storage
+                .upload(
+                    format!("./{}", record.name),
+                    UploadRequest::default()
+                        .with_content_type(Some("image/png"))
+                        .with_data(png()),
+                )
+                .await
+                .unwrap();
+
+            self.metadata.put(record).await.unwrap();
+        }
+
+        async fn get(&self, path: &str) -> reqwest::Response {
+            reqwest::get(format!("{}{path}", self.url)).await.unwrap()
+        }
+
+        /// Uploads `png()` as many times as `images` with the `default` uploader's key, after
+        /// the given form fields.
+        async fn upload(&self, fields: &[(&'static str, &'static str)], images: usize) -> reqwest::Response {
+            let form = fields
+                .iter()
+                .fold(reqwest::multipart::Form::new(), |form, (name, value)| {
+                    form.text(*name, *value)
+                });
+
+            let form = (0..images).fold(form, |form, _| {
+                form.part(
+                    "file",
+                    reqwest::multipart::Part::bytes(png())
+                        .file_name("cat.png")
+                        .mime_str("image/png")
+                        .unwrap(),
+                )
+            });
+
+            reqwest::Client::new()
+                .post(format!("{}/images/upload", self.url))
+                .header(reqwest::header::AUTHORIZATION, "abc")
+                .multipart(form)
+                .send()
+                .await
+                .unwrap()
+        }
+    }
+
+    fn png() -> Vec<u8> {
+        let mut out = Cursor::new(Vec::new());
+        RgbImage::new(4, 4).write_to(&mut out, image::ImageFormat::Png).unwrap();
+
+        out.into_inner()
+    }
+
+    fn record(name: &str) -> metadata::Image {
+        metadata::Image {
+            name: name.to_owned(),
+            uploader: Some(String::from("default")),
+            original_filename: None,
+            uploaded_at: Utc::now(),
+            size: png().len() as u64,
+            width: Some(4),
+            height: Some(4),
+            sha256: String::from("00"),
+            content_type: String::from("image/png"),
+            alias_of: None,
+            expires_at: None,
+            max_views: None,
+            views: 0,
+            preview_views: 0,
+            private: false,
+            password: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_images_are_gone() {
+        let server = Server::start(r#"uploader_key = "abc""#).await;
+        server
+            .store(&metadata::Image {
+                expires_at: Some(Utc::now() - TimeDelta::minutes(1)),
+                ..record("expired.png")
+            })
+            .await;
+
+        for path in [
+            "/images/expired.png/info",
+            "/images/expired.png/thumbnail",
+            "/images/expired.png",
+        ] {
+            assert_eq!(server.get(path).await.status(), StatusCode::GONE, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn images_are_gone_once_they_run_out_of_views() {
+        let server = Server::start(r#"uploader_key = "abc""#).await;
+        server
+            .store(&metadata::Image {
+                max_views: Some(2),
+                ..record("limited.png")
+            })
+            .await;
+
+        // neither the information nor `HEAD` requests use up a view
+        assert_eq!(server.get("/images/limited.png/info").await.status(), StatusCode::OK);
+        let head = reqwest::Client::new()
+            .head(format!("{}/images/limited.png", server.url))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(head.status(), StatusCode::OK);
+        for _ in 0..2 {
+            let response = server.get("/images/limited.png").await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.bytes().await.unwrap(), png());
+        }
+
+        // the image is deleted in the background once it's requested after running out
+        assert_eq!(server.get("/images/limited.png/info").await.status(), StatusCode::GONE);
+        assert_eq!(server.get("/images/limited.png").await.status(), StatusCode::GONE);
+    }
+
+    #[tokio::test]
+    async fn preview_bots_only_get_their_allowance() {
+        const DISCORD: &str = "Mozilla/5.0 (compatible; Discordbot/2.0; +https://discordapp.com)";
+
+        let preview = |server: &Server, name: &str| {
+            reqwest::Client::new()
+                .get(format!("{}/images/{name}", server.url))
+                .header(reqwest::header::USER_AGENT, DISCORD)
+                .send()
+        };
+
+        // bots get a few previews for free by default, so unfurling a link doesn't use
+        // up the only view
+        let server = Server::start(r#"uploader_key = "abc""#).await;
+        server
+            .store(&metadata::Image {
+                max_views: Some(1),
+                ..record("limited.png")
+            })
+            .await;
+
+        for _ in 0..3 {
+            assert_eq!(preview(&server, "limited.png").await.unwrap().status(), StatusCode::OK);
+        }
+
+        assert_eq!(server.get("/images/limited.png/info").await.status(), StatusCode::OK);
+
+        // the fourth preview used up the only view
+        assert_eq!(preview(&server, "limited.png").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(server.get("/images/limited.png/info").await.status(), StatusCode::GONE);
+
+        // and they count as regular views without an allowance
+        let server = Server::start(
+            r#"uploader_key = "abc"
+            [uploads]
+            preview_views = 0"#,
+        )
+        .await;
+
+        server
+            .store(&metadata::Image {
+                max_views: Some(1),
+                ..record("limited.png")
+            })
+            .await;
+
+        assert_eq!(preview(&server, "limited.png").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(server.get("/images/limited.png/info").await.status(), StatusCode::GONE);
+    }
+
+    #[tokio::test]
+    async fn only_owners_can_sign_images() {
+        let server = Server::start(
+            r#"uploader_key = "abc"
+            max_signed_ttl = "1d"
+
+            [[uploaders]]
+            name = "noel"
+            keys = ["noel"]"#,
+        )
+        .await;
+
+        server.store(&record("default.png")).await;
+        server
+            .store(&metadata::Image {
+                uploader: Some(String::from("noel")),
+                ..record("noel.png")
+            })
+            .await;
+
+        let sign = |key: &'static str, path: &str| {
+            reqwest::Client::new()
+                .post(format!("{}/images/{path}", server.url))
+                .header(reqwest::header::AUTHORIZATION, key)
+                .send()
+        };
+
+        assert_eq!(
+            sign("noel", "default.png/sign?w=2").await.unwrap().status(),
+            StatusCode::FORBIDDEN
+        );
+
+        assert_eq!(
+            sign("noel", "noel.png/sign?w=2").await.unwrap().status(),
+            StatusCode::OK
+        );
+
+        assert_eq!(sign("abc", "noel.png/sign?w=2").await.unwrap().status(), StatusCode::OK);
+
+        // signed urls can't outlive `max_signed_ttl`
+        assert_eq!(
+            sign("noel", "noel.png/sign?expires_in=1h").await.unwrap().status(),
+            StatusCode::OK
+        );
+
+        assert_eq!(
+            sign("noel", "noel.png/sign?expires_in=2d").await.unwrap().status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn protected_images_ask_for_their_password() {
+        let server = Server::start(r#"uploader_key = "abc""#).await;
+        server
+            .store(&metadata::Image {
+                password: Some(crate::config::hash_key("hunter2").unwrap()),
+                ..record("protected.png")
+            })
+            .await;
+
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+
+        let get = |path: &str| client.get(format!("{}{path}", server.url));
+
+        // browsers get a page that posts the password back to the same URL
+        let response = get("/images/protected.png").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[reqwest::header::CONTENT_TYPE], "application/json");
+
+        let response = get("/images/protected.png?w=2")
+            .header(reqwest::header::ACCEPT, "text/html")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let page = response.text().await.unwrap();
+        assert!(page.contains(r#"<form method="post">"#));
+
+        let response = get("/images/protected.png?password=hunter3").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let post = |password: &'static str| {
+            client
+                .post(format!("{}/images/protected.png?w=2&password=hunter2", server.url))
+                .form(&[("password", password)])
+                .send()
+        };
+
+        let response = post("hunter3").await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // which sends the browser back with a cookie, and without the password in the URL
+        let response = post("hunter2").await.unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert!(response.headers().contains_key(reqwest::header::SET_COOKIE));
+        assert!(
+            response.headers()[reqwest::header::LOCATION]
+                .to_str()
+                .unwrap()
+                .ends_with("/images/protected.png?w=2")
+        );
+
+        // the right password sets a cookie that is enough on its own afterwards
+        let response = get("/images/protected.png")
+            .header(super::super::password::HEADER, "hunter2")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let cookie = response.headers()[reqwest::header::SET_COOKIE].to_str().unwrap();
+        assert!(cookie.contains("Path=/images/protected.png;"));
+
+        let cookie = cookie.split_once(';').unwrap().0.to_owned();
+        let response = get("/images/protected.png")
+            .header(reqwest::header::COOKIE, &cookie)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.bytes().await.unwrap(), png());
+
+        // the thumbnail redirects to the image with the cookie, but never the password
+        let response = get("/images/protected.png/thumbnail")
+            .header(super::super::password::HEADER, "hunter2")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert!(response.headers().contains_key(reqwest::header::SET_COOKIE));
+        assert!(
+            response.headers()[reqwest::header::LOCATION]
+                .to_str()
+                .unwrap()
+                .ends_with("/images/protected.png")
+        );
+
+        // only uploaders can see the information, and never the password's hash
+        let response = get("/images/protected.png/info").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let info: serde_json::Value = get("/images/protected.png/info")
+            .header(reqwest::header::AUTHORIZATION, "abc")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(info["password_protected"], true);
+        assert!(info.get("password").is_none());
+
+        // too many wrong passwords have to wait, even if the next one is right
+        for _ in 0..5 {
+            let response = get("/images/protected.png?password=hunter3").send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+
+        let response = get("/images/protected.png?password=hunter2").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn uploads_never_take_the_same_name() {
+        let server = Server::start(
+            r#"uploader_key = "abc"
+            [naming]
+            strategy = "content-hash""#,
+        )
+        .await;
+
+        // every upload of the same image wants the same name first
+        let uploads = futures_util::future::join_all((0..4).map(|_| server.upload(&[], 1))).await;
+        let mut names = Vec::new();
+        for upload in uploads {
+            assert_eq!(upload.status(), StatusCode::OK);
+
+            let upload: serde_json::Value = upload.json().await.unwrap();
+            names.push(upload["filename"].as_str().unwrap().to_owned());
+        }
+
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn only_one_upload_gets_a_vanity_name() {
+        let server = Server::start(r#"uploader_key = "abc""#).await;
+        let uploads = futures_util::future::join_all((0..4).map(|_| server.upload(&[("name", "release")], 1))).await;
+
+        let mut statuses = uploads.iter().map(reqwest::Response::status).collect::<Vec<_>>();
+        statuses.sort();
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::OK,
+                StatusCode::CONFLICT,
+                StatusCode::CONFLICT,
+                StatusCode::CONFLICT
+            ]
+        );
+
+        assert_eq!(server.get("/images/release.png").await.bytes().await.unwrap(), png());
+    }
+
+    #[tokio::test]
+    async fn uploads_of_many_images_are_rolled_back() {
+        // the third image can't be named, since the first two took the only names that
+        // an image with its contents can get
+        let server = Server::start(
+            r#"uploader_key = "abc"
+            [naming]
+            strategy = "content-hash"
+            alphabet = "a""#,
+        )
+        .await;
+
+        let upload = server.upload(&[], 3).await;
+        assert_eq!(upload.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(server.metadata.list(None, false, 10).await.unwrap().is_empty());
+
+        let upload: serde_json::Value = server.upload(&[("album", "true")], 2).await.json().await.unwrap();
+        assert_eq!(upload["images"].as_array().unwrap().len(), 2);
+
+        let album = upload["album_url"].as_str().unwrap();
+        let id = album.rsplit_once('/').unwrap().1;
+        assert_eq!(server.get(&format!("/albums/{id}")).await.status(), StatusCode::OK);
+        assert_eq!(server.get("/albums/a.png").await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn put_images() {
+        let server = Server::start(
+            r#"uploader_key = "abc"
+
+            [[uploaders]]
+            name = "noel"
+            keys = ["noel"]
+
+            [server]
+            body_limit = 1024"#,
+        )
+        .await;
+
+        let put = |key: &'static str, path: &str, body: Vec<u8>| {
+            reqwest::Client::new()
+                .put(format!("{}{path}", server.url))
+                .header(reqwest::header::AUTHORIZATION, key)
+                .body(body)
+        };
+
+        // uploaders that can't choose names only keep it as the original filename
+        let upload: serde_json::Value = put("noel", "/images/shot.png", png())
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let url = upload["filename"].as_str().unwrap();
+        assert!(!url.ends_with("/images/shot.png"));
+
+        let name = url.rsplit_once('/').unwrap().1;
+        let record = server.metadata.get(name).await.unwrap().unwrap();
+        assert_eq!(record.original_filename.as_deref(), Some("shot.png"));
+
+        let response = put("abc", "/images/shot.png", png())
+            .header(reqwest::header::ACCEPT, "text/plain")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.text().await.unwrap(),
+            "http://localhost:3621/images/shot.png\n"
+        );
+
+        let mut big = png();
+        big.resize(2048, 0);
+
+        let response = put("abc", "/images", big).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn images_uploaded_before_metadata_are_listed() {
+        let server = Server::start(r#"uploader_key = "abc""#).await;
+        assert_eq!(server.upload(&[], 1).await.status(), StatusCode::OK);
+
+        // images that were uploaded before metadata was kept only exist in the storage service
+        server
+            .storage
+            .upload(
+                "./legacy.png",
+                UploadRequest::default()
+                    .with_content_type(Some("image/png"))
+                    .with_data(png()),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(backfill::backfill(&server.storage, &server.metadata).await.unwrap(), 1);
+        assert_eq!(backfill::backfill(&server.storage, &server.metadata).await.unwrap(), 0);
+
+        let record = server.metadata.get("legacy.png").await.unwrap().unwrap();
+        assert_eq!(record.uploader, None);
+        assert_eq!((record.width, record.height), (Some(4), Some(4)));
+
+        let listing = reqwest::Client::new()
+            .get(format!("{}/images", server.url))
+            .header(reqwest::header::AUTHORIZATION, "abc")
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+
+        let images = listing["images"].as_array().unwrap();
+        assert_eq!(images.len(), 2);
+        assert!(images.iter().any(|image| image["name"] == "legacy.png"));
+    }
+
+    #[tokio::test]
+    async fn private_images_are_never_deduplicated() {
+        let server = Server::start(
+            r#"uploader_key = "abc"
+            [uploads]
+            deduplicate = "alias""#,
+        )
+        .await;
+
+        let server = &server;
+        let upload = |fields| async move {
+            let response = server.upload(fields, 1).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            response.json::<serde_json::Value>().await.unwrap()
+        };
+
+        let private = upload(&[("private", "true")]).await;
+        assert_eq!(private["deduplicated"], false);
+
+        // the only identical image is private, so the next one holds its own data
+        let public = upload(&[]).await;
+        assert_eq!(public["deduplicated"], false);
+
+        let alias = upload(&[]).await;
+        assert_eq!(alias["deduplicated"], true);
+        assert!(alias["deletion_url"].is_string());
+    }
+
+    #[tokio::test]
+    async fn thumbnails_get_the_same_headers_as_images() {
+        let server = Server::start(r#"uploader_key = "abc""#).await;
+        server.store(&record("thumbnail.png")).await;
+        server
+            .storage
+            .upload(
+                super::super::thumbnail::path("00", 256),
+                UploadRequest::default()
+                    .with_content_type(Some("image/png"))
+                    .with_data(png()),
+            )
+            .await
+            .unwrap();
+
+        let thumbnail = server.get("/images/thumbnail.png/thumbnail").await;
+        assert_eq!(thumbnail.status(), StatusCode::OK);
+
+        let headers = thumbnail.headers();
+        assert_eq!(headers["x-content-type-options"], "nosniff");
+        assert!(headers.contains_key(reqwest::header::CONTENT_SECURITY_POLICY));
+        assert!(headers.contains_key(reqwest::header::CACHE_CONTROL));
+
+        // thumbnails have their own entity tag, which they can be revalidated with
+        let etag = headers[reqwest::header::ETAG].clone();
+        assert_ne!(etag, "\"00\"");
+
+        let revalidated = reqwest::Client::new()
+            .get(format!("{}/images/thumbnail.png/thumbnail", server.url))
+            .header(reqwest::header::IF_NONE_MATCH, etag)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(revalidated.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[tokio::test]
+    async fn images_are_only_deleted_once() {
+        let server = Server::start(r#"uploader_key = "abc""#).await;
+        server.store(&record("owner.png")).await;
+        server
+            .metadata
+            .put(&metadata::Image {
+                alias_of: Some(String::from("owner.png")),
+                ..record("alias.png")
+            })
+            .await
+            .unwrap();
+
+        // like the reaper deleting an image while its uploader does
+        let owner = server.metadata.get("owner.png").await.unwrap().unwrap();
+        let deletions = futures_util::future::join_all(
+            (0..4).map(|_| super::super::deletion::delete(&server.storage, &server.metadata, &[256], &owner)),
+        )
+        .await;
+
+        assert!(deletions.iter().all(Result::is_ok));
+        assert!(server.metadata.get("owner.png").await.unwrap().is_none());
+
+        let alias = server.metadata.get("alias.png").await.unwrap().unwrap();
+        assert_eq!(alias.alias_of, None);
+        assert_eq!(server.get("/images/alias.png").await.bytes().await.unwrap(), png());
+    }
 }
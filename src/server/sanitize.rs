@@ -0,0 +1,491 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    spool::Spool,
+    transform::{self, Pool},
+};
+use crate::{config::transforms::Format, format::ImageFormat};
+use image::{ImageReader, ImageResult, metadata::Orientation};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+/// Quality that JPEG images are re-encoded with when their orientation is applied.
+const JPEG_QUALITY: u8 = 90;
+
+/// Largest EXIF payload that is read to find the image's orientation. Larger payloads
+/// are still removed, their orientation is ignored.
+const MAX_EXIF_LENGTH: u64 = 1024 * 1024;
+
+/// Image that went through [`sanitize`].
+pub struct Sanitized {
+    pub spool: Spool,
+
+    /// whether any metadata was removed from the image.
+    pub stripped: bool,
+}
+
+/// What was found while removing the metadata of an image.
+struct Stripped {
+    /// EXIF data, starting at the TIFF header.
+    exif: Option<Vec<u8>>,
+    removed: bool,
+    animated: bool,
+}
+
+/// Removes EXIF (which GPS coordinates are kept in), XMP, IPTC and comments from JPEG, PNG
+/// and WebP images by dropping the segments or chunks that hold them, so the pixel data is
+/// kept as-is. Images in other formats, or that can't be walked through, aren't touched.
+///
+/// If the EXIF data rotates or flips the image, then the image is re-encoded with the
+/// orientation applied, since it would be displayed the wrong way once the EXIF data is gone.
+pub async fn sanitize(pool: &Pool, format: ImageFormat, spool: Spool) -> io::Result<Sanitized> {
+    if !matches!(format, ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::Webp) {
+        return Ok(Sanitized { spool, stripped: false });
+    }
+
+    let (spool, output, stripped) = tokio::task::spawn_blocking(move || {
+        let output = Spool::new()?;
+        let stripped = strip(format, spool.path(), output.path())?;
+
+        Ok::<_, io::Error>((spool, output, stripped))
+    })
+    .await
+    .map_err(io::Error::other)??;
+
+    let Some(stripped) = stripped else {
+        warn!(%format, "image is malformed, not removing its metadata");
+        return Ok(Sanitized { spool, stripped: false });
+    };
+
+    if !stripped.removed {
+        return Ok(Sanitized { spool, stripped: false });
+    }
+
+    let orientation = stripped
+        .exif
+        .as_deref()
+        .and_then(Orientation::from_exif_chunk)
+        .filter(|orientation| *orientation != Orientation::NoTransforms);
+
+    let Some(orientation) = orientation.filter(|_| !stripped.animated) else {
+        return Ok(Sanitized {
+            spool: output,
+            stripped: true,
+        });
+    };
+
+    let (output, rotated) = pool
+        .run(move || {
+            let rotated = orient(output.path(), format, orientation);
+            (output, rotated)
+        })
+        .await;
+
+    match rotated {
+        Ok(rotated) => Ok(Sanitized {
+            spool: rotated,
+            stripped: true,
+        }),
+
+        Err(e) => {
+            warn!(error = %e, %format, "unable to apply EXIF orientation, keeping the image as-is");
+            Ok(Sanitized {
+                spool: output,
+                stripped: true,
+            })
+        }
+    }
+}
+
+/// Writes the image at `input` without its metadata to `output`. Returns `None` if the
+/// image is malformed.
+fn strip(format: ImageFormat, input: &Path, output: &Path) -> io::Result<Option<Stripped>> {
+    let mut reader = BufReader::new(File::open(input)?);
+    let mut writer = BufWriter::new(File::create(output)?);
+    let stripped = match format {
+        ImageFormat::Jpeg => strip_jpeg(&mut reader, &mut writer),
+        ImageFormat::Png => strip_png(&mut reader, &mut writer),
+        ImageFormat::Webp => strip_webp(&mut reader, &mut writer),
+        _ => unreachable!("only JPEG, PNG and WebP images are stripped"),
+    };
+
+    match stripped {
+        Ok(stripped) => {
+            writer.flush()?;
+            Ok(Some(stripped))
+        }
+
+        Err(e) if matches!(e.kind(), io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn orient(path: &Path, format: ImageFormat, orientation: Orientation) -> ImageResult<Spool> {
+    let mut image = ImageReader::open(path)?.with_guessed_format()?.decode()?;
+    image.apply_orientation(orientation);
+
+    let format = match format {
+        ImageFormat::Jpeg => Format::Jpeg,
+        ImageFormat::Webp => Format::Webp,
+        _ => Format::Png,
+    };
+
+    let (data, _) = transform::encode(&image, format, JPEG_QUALITY)?;
+    let spool = Spool::new()?;
+    std::fs::write(spool.path(), data)?;
+
+    Ok(spool)
+}
+
+fn malformed() -> io::Error {
+    io::Error::from(io::ErrorKind::InvalidData)
+}
+
+/// Copies exactly `length` bytes from `reader` to `writer`.
+fn copy_exact(reader: &mut impl Read, writer: &mut impl Write, length: u64) -> io::Result<()> {
+    if io::copy(&mut reader.take(length), writer)? != length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(())
+}
+
+/// Reads a payload of `length` bytes if it is small enough to keep in memory, skipping
+/// over it otherwise.
+fn read_payload(reader: &mut impl Read, length: u64) -> io::Result<Option<Vec<u8>>> {
+    if length > MAX_EXIF_LENGTH {
+        copy_exact(reader, &mut io::sink(), length)?;
+        return Ok(None);
+    }
+
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload)?;
+
+    Ok(Some(payload))
+}
+
+/// Fills `buf` from `reader`, returning `false` if `reader` had already ended.
+fn read_header(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    if reader.read(&mut buf[..1])? == 0 {
+        return Ok(false);
+    }
+
+    reader.read_exact(&mut buf[1..])?;
+    Ok(true)
+}
+
+/// Drops the APP1 (EXIF and XMP), APP13 (IPTC) and COM segments of a JPEG image.
+fn strip_jpeg(reader: &mut impl Read, writer: &mut impl Write) -> io::Result<Stripped> {
+    let mut exif = None;
+    let mut removed = false;
+
+    let mut soi = [0; 2];
+    reader.read_exact(&mut soi)?;
+    writer.write_all(&soi)?;
+
+    let mut byte = [0; 1];
+    loop {
+        reader.read_exact(&mut byte)?;
+        if byte[0] != 0xFF {
+            return Err(malformed());
+        }
+
+        // markers can be padded with any amount of fill bytes
+        let marker = loop {
+            reader.read_exact(&mut byte)?;
+            if byte[0] != 0xFF {
+                break byte[0];
+            }
+        };
+
+        // everything from the start of the scan (or the end of the image) is copied as-is
+        if marker == 0xDA || marker == 0xD9 {
+            writer.write_all(&[0xFF, marker])?;
+            io::copy(reader, writer)?;
+            break;
+        }
+
+        let mut length = [0; 2];
+        reader.read_exact(&mut length)?;
+
+        let payload = u64::from(u16::from_be_bytes(length))
+            .checked_sub(2)
+            .ok_or_else(malformed)?;
+        match marker {
+            0xE1 => {
+                let segment = read_payload(reader, payload)?;
+                if let Some(tiff) = segment.as_deref().and_then(|segment| segment.strip_prefix(b"Exif\0\0")) {
+                    exif.get_or_insert_with(|| tiff.to_vec());
+                }
+
+                removed = true;
+            }
+
+            0xED | 0xFE => {
+                copy_exact(reader, &mut io::sink(), payload)?;
+                removed = true;
+            }
+
+            _ => {
+                writer.write_all(&[0xFF, marker])?;
+                writer.write_all(&length)?;
+                copy_exact(reader, writer, payload)?;
+            }
+        }
+    }
+
+    Ok(Stripped {
+        exif,
+        removed,
+        animated: false,
+    })
+}
+
+/// Drops the `eXIf`, `tEXt`, `zTXt`, `iTXt` (which XMP is kept in) and `tIME` chunks of a
+/// PNG image, along with anything after the `IEND` chunk.
+fn strip_png(reader: &mut impl Read, writer: &mut impl Write) -> io::Result<Stripped> {
+    let mut exif = None;
+    let mut removed = false;
+    let mut animated = false;
+
+    let mut signature = [0; 8];
+    reader.read_exact(&mut signature)?;
+    writer.write_all(&signature)?;
+
+    // length and type
+    let mut header = [0; 8];
+    while read_header(reader, &mut header)? {
+        let length = u64::from(u32::from_be_bytes(header[..4].try_into().unwrap()));
+        let ty = &header[4..8];
+
+        // the CRC comes after the chunk's data
+        match ty {
+            b"eXIf" => {
+                if let Some(payload) = read_payload(reader, length)? {
+                    exif.get_or_insert(payload);
+                }
+
+                copy_exact(reader, &mut io::sink(), 4)?;
+                removed = true;
+            }
+
+            b"tEXt" | b"zTXt" | b"iTXt" | b"tIME" => {
+                copy_exact(reader, &mut io::sink(), length + 4)?;
+                removed = true;
+            }
+
+            _ => {
+                animated |= ty == b"acTL";
+                writer.write_all(&header)?;
+                copy_exact(reader, writer, length + 4)?;
+            }
+        }
+
+        if ty == b"IEND" {
+            removed |= reader.read(&mut [0])? > 0;
+            break;
+        }
+    }
+
+    Ok(Stripped {
+        exif,
+        removed,
+        animated,
+    })
+}
+
+/// Drops the `EXIF` and `XMP ` chunks of a WebP image and clears their flags in the `VP8X`
+/// chunk.
+fn strip_webp(reader: &mut impl Read, writer: &mut (impl Write + Seek)) -> io::Result<Stripped> {
+    let mut exif = None;
+    let mut removed = false;
+    let mut animated = false;
+
+    let mut header = [0; 12];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != b"RIFF" || &header[8..12] != b"WEBP" {
+        return Err(malformed());
+    }
+
+    // the size is written once every chunk has been
+    writer.write_all(&header)?;
+
+    // anything after the RIFF container is dropped
+    let size = u64::from(u32::from_le_bytes(header[4..8].try_into().unwrap()));
+    let mut reader = reader.take(size.checked_sub(4).ok_or_else(malformed)?);
+
+    let mut chunk = [0; 8];
+    while read_header(&mut reader, &mut chunk)? {
+        let length = u64::from(u32::from_le_bytes(chunk[4..8].try_into().unwrap()));
+
+        // chunks are padded to an even length
+        let padded = length + (length & 1);
+        match &chunk[..4] {
+            b"EXIF" => {
+                if let Some(payload) = read_payload(&mut reader, length)? {
+                    let tiff = payload.strip_prefix(b"Exif\0\0").unwrap_or(&payload);
+                    exif.get_or_insert_with(|| tiff.to_vec());
+                }
+
+                copy_exact(&mut reader, &mut io::sink(), padded - length)?;
+                removed = true;
+            }
+
+            b"XMP " => {
+                copy_exact(&mut reader, &mut io::sink(), padded)?;
+                removed = true;
+            }
+
+            b"VP8X" => {
+                let mut payload = read_payload(&mut reader, padded)?.ok_or_else(malformed)?;
+                let flags = payload.first_mut().ok_or_else(malformed)?;
+                animated = *flags & 0x02 != 0;
+
+                // EXIF and XMP flags
+                *flags &= !(0x08 | 0x04);
+
+                writer.write_all(&chunk)?;
+                writer.write_all(&payload)?;
+            }
+
+            _ => {
+                writer.write_all(&chunk)?;
+                copy_exact(&mut reader, writer, padded)?;
+            }
+        }
+    }
+
+    let end = writer.stream_position()?;
+    let size = u32::try_from(end - 8).map_err(|_| malformed())?;
+
+    writer.seek(SeekFrom::Start(4))?;
+    writer.write_all(&size.to_le_bytes())?;
+    writer.seek(SeekFrom::Start(end))?;
+
+    Ok(Stripped {
+        exif,
+        removed,
+        animated,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{strip_jpeg, strip_png, strip_webp};
+    use image::{RgbImage, metadata::Orientation};
+    use std::io::Cursor;
+
+    fn encode(format: image::ImageFormat) -> Vec<u8> {
+        let mut out = Cursor::new(Vec::new());
+        RgbImage::new(8, 4).write_to(&mut out, format).unwrap();
+
+        out.into_inner()
+    }
+
+    #[test]
+    fn jpeg() {
+        // big-endian TIFF header with a single IFD entry: orientation (0x0112) = 6
+        let tiff = b"MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0";
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend_from_slice(&((2 + 6 + tiff.len()) as u16).to_be_bytes());
+        app1.extend_from_slice(b"Exif\0\0");
+        app1.extend_from_slice(tiff);
+
+        let original = encode(image::ImageFormat::Jpeg);
+        let mut data = original[..2].to_vec();
+        data.extend_from_slice(&app1);
+        data.extend_from_slice(&original[2..]);
+
+        let mut out = Vec::new();
+        let stripped = strip_jpeg(&mut &data[..], &mut out).unwrap();
+        assert!(stripped.removed);
+        assert_eq!(out, original);
+        assert_eq!(
+            Orientation::from_exif_chunk(&stripped.exif.unwrap()),
+            Some(Orientation::Rotate90)
+        );
+
+        assert!(!strip_jpeg(&mut &original[..], &mut Vec::new()).unwrap().removed);
+    }
+
+    #[test]
+    fn truncated_jpeg() {
+        let original = encode(image::ImageFormat::Jpeg);
+
+        // APP1 segments whose length doesn't even cover the length itself
+        for length in [0u16, 1] {
+            let mut data = original[..2].to_vec();
+            data.extend_from_slice(&[0xFF, 0xE1]);
+            data.extend_from_slice(&length.to_be_bytes());
+            data.extend_from_slice(&original[2..]);
+
+            assert!(strip_jpeg(&mut &data[..], &mut Vec::new()).is_err());
+        }
+
+        // APP1 segment that is shorter than the `Exif\0\0` prefix
+        let mut data = original[..2].to_vec();
+        data.extend_from_slice(&[0xFF, 0xE1, 0x00, 0x04, b'E', b'x']);
+        data.extend_from_slice(&original[2..]);
+
+        let mut out = Vec::new();
+        let stripped = strip_jpeg(&mut &data[..], &mut out).unwrap();
+        assert!(stripped.removed && stripped.exif.is_none());
+        assert_eq!(out, original);
+
+        // APP1 segment that is cut off by the end of the image
+        let mut data = original[..2].to_vec();
+        data.extend_from_slice(&[0xFF, 0xE1, 0x01, 0x00, b'E', b'x', b'i', b'f']);
+        assert!(strip_jpeg(&mut &data[..], &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn png() {
+        let original = encode(image::ImageFormat::Png);
+
+        // tEXt chunk with a bogus CRC, which isn't checked
+        let mut data = original[..33].to_vec();
+        data.extend_from_slice(&[0, 0, 0, 8]);
+        data.extend_from_slice(b"tEXtAuthor\0a");
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&original[33..]);
+
+        let mut out = Vec::new();
+        let stripped = strip_png(&mut &data[..], &mut out).unwrap();
+        assert!(stripped.removed);
+        assert_eq!(out, original);
+        assert!(image::load_from_memory(&out).is_ok());
+    }
+
+    #[test]
+    fn webp() {
+        let original = encode(image::ImageFormat::WebP);
+
+        // odd-length EXIF chunk, which is padded to an even length
+        let mut data = original.clone();
+        data.extend_from_slice(b"EXIF\x03\0\0\0abc\0");
+
+        let size = (data.len() - 8) as u32;
+        data[4..8].copy_from_slice(&size.to_le_bytes());
+
+        let mut out = Cursor::new(Vec::new());
+        let stripped = strip_webp(&mut &data[..], &mut out).unwrap();
+        assert!(stripped.removed);
+        assert_eq!(out.into_inner(), original);
+    }
+}
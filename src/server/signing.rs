@@ -0,0 +1,113 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::Duration;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How long signed URLs of private images are valid for if the uploader doesn't say.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// How long signed URLs of private images are valid for if the uploader doesn't say, which
+/// is [`DEFAULT_TTL`] unless `max_signed_ttl` is shorter.
+pub fn default_ttl(config: &crate::config::Config) -> Duration {
+    DEFAULT_TTL.min(config.max_signed_ttl.into())
+}
+
+/// Signs the query parameters `params` of a URL that points to the image called `name`. The
+/// returned signature is meant to be sent in the `sig` query parameter.
+///
+/// The order of `params` matters, so callers should always give them in the same order.
+pub fn sign(key: &str, name: &str, params: &[(&str, String)]) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(mac(key, name, params).finalize().into_bytes())
+}
+
+/// Checks whether if `signature` was created by [`sign`] for the same image and parameters.
+pub fn verify(key: &str, name: &str, params: &[(&str, String)], signature: &str) -> bool {
+    let Ok(signature) = BASE64_URL_SAFE_NO_PAD.decode(signature) else {
+        return false;
+    };
+
+    mac(key, name, params).verify_slice(&signature).is_ok()
+}
+
+/// Signs like [`sign`] for a URL that is only valid until `expires` (in seconds since the
+/// Unix epoch), which is meant to be sent in the `expires` query parameter.
+pub fn sign_until(key: &str, name: &str, params: &[(&str, String)], expires: i64) -> String {
+    sign(key, name, &with_expiry(params, expires))
+}
+
+/// Checks whether if `signature` was created by [`sign_until`] for the same image, parameters
+/// and expiry, and that the expiry hasn't passed yet.
+pub fn verify_until(key: &str, name: &str, params: &[(&str, String)], expires: i64, signature: &str) -> bool {
+    expires > Utc::now().timestamp() && verify(key, name, &with_expiry(params, expires), signature)
+}
+
+fn with_expiry<'a>(params: &[(&'a str, String)], expires: i64) -> Vec<(&'a str, String)> {
+    let mut params = params.to_vec();
+    params.push(("expires", expires.to_string()));
+
+    params
+}
+
+fn mac(key: &str, name: &str, params: &[(&str, String)]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key.as_bytes()).expect("HMAC can take a key of any size");
+    mac.update(name.as_bytes());
+
+    for (key, value) in params {
+        mac.update(b"\n");
+        mac.update(key.as_bytes());
+        mac.update(b"=");
+        mac.update(value.as_bytes());
+    }
+
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sign, sign_until, verify, verify_until};
+    use chrono::Utc;
+
+    #[test]
+    fn sign_and_verify() {
+        let params = [("w", String::from("200")), ("format", String::from("webp"))];
+        let signature = sign("key", "abcdef.png", &params);
+
+        assert!(verify("key", "abcdef.png", &params, &signature));
+        assert!(!verify("other key", "abcdef.png", &params, &signature));
+        assert!(!verify("key", "ghijkl.png", &params, &signature));
+        assert!(!verify("key", "abcdef.png", &params[..1], &signature));
+        assert!(!verify("key", "abcdef.png", &params, "not base64!"));
+    }
+
+    #[test]
+    fn expiring() {
+        let expires = Utc::now().timestamp() + 60;
+        let signature = sign_until("key", "abcdef.png", &[], expires);
+
+        assert!(verify_until("key", "abcdef.png", &[], expires, &signature));
+        assert!(!verify_until("key", "abcdef.png", &[], expires + 1, &signature));
+        assert!(!verify("key", "abcdef.png", &[], &signature));
+
+        let expired = Utc::now().timestamp() - 1;
+        let signature = sign_until("key", "abcdef.png", &[], expired);
+        assert!(!verify_until("key", "abcdef.png", &[], expired, &signature));
+    }
+}
@@ -0,0 +1,88 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use axum::body::Bytes;
+use sha2::{Digest, Sha256};
+use std::{io, path::Path};
+use tempfile::TempPath;
+use tokio::io::AsyncReadExt as _;
+
+/// Uploaded image that is kept in a temporary file while it's being processed, so that
+/// it doesn't have to be kept in memory. The file is removed once this is dropped.
+pub struct Spool(TempPath);
+
+impl Spool {
+    /// Creates an empty spool in the system's temporary directory.
+    pub fn new() -> io::Result<Spool> {
+        tempfile::Builder::new()
+            .prefix("ume-")
+            .tempfile()
+            .map(|file| Spool(file.into_temp_path()))
+    }
+
+    /// Creates a spool that holds `data`.
+    pub async fn from_bytes(data: &[u8]) -> io::Result<Spool> {
+        let spool = Spool::new()?;
+        tokio::fs::write(spool.path(), data).await?;
+
+        Ok(spool)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Reads all of the contents of the spool into memory.
+    pub async fn bytes(&self) -> io::Result<Bytes> {
+        tokio::fs::read(self.path()).await.map(Bytes::from)
+    }
+
+    /// Returns how large the spool is, along with the SHA-256 digest of its contents.
+    pub async fn digest(&self) -> io::Result<(u64, String)> {
+        let mut file = tokio::fs::File::open(self.path()).await?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 64 * 1024];
+        let mut size = 0;
+
+        loop {
+            let read = file.read(&mut buf).await?;
+            if read == 0 {
+                break;
+            }
+
+            hasher.update(&buf[..read]);
+            size += read as u64;
+        }
+
+        Ok((size, format!("{:x}", hasher.finalize())))
+    }
+
+    /// Returns the width and height of the image in the spool, if they can be read
+    /// from its header.
+    pub async fn dimensions(&self) -> Option<(u32, u32)> {
+        let path = self.path().to_owned();
+        tokio::task::spawn_blocking(move || {
+            image::ImageReader::open(path)
+                .ok()?
+                .with_guessed_format()
+                .ok()?
+                .into_dimensions()
+                .ok()
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+}
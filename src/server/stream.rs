@@ -0,0 +1,485 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config::storage;
+use axum::body::Bytes;
+use azalia::remi::{
+    StorageService,
+    azure::{self, core::StatusCode},
+    core::{StorageService as _, UploadRequest},
+    fs,
+    gridfs::{
+        self,
+        mongodb::{
+            Client,
+            bson::{Bson, doc},
+            gridfs::GridFsBucket,
+            options::GridFsUploadOptions,
+        },
+    },
+    s3::{
+        self,
+        aws::s3::{Client as S3Client, primitives::ByteStream as S3ByteStream, types::ObjectCannedAcl},
+    },
+};
+use futures_util::{
+    StreamExt, TryStreamExt,
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    stream::{self, BoxStream},
+};
+use std::{
+    io,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use tokio::io::{AsyncReadExt as _, AsyncSeekExt as _, SeekFrom};
+use tokio_util::{
+    compat::{FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt},
+    io::ReaderStream,
+};
+
+/// Stream of the contents of an [`Object`].
+pub type ByteStream = BoxStream<'static, io::Result<Bytes>>;
+
+/// Reads and writes images in the storage service as streams, so that they don't have to
+/// be kept in memory while they're being sent or received. `remi` can only load and upload
+/// blobs as a whole, so this talks to the storage backends directly, with the same client
+/// that the storage service uses.
+#[derive(Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum Streamer {
+    Filesystem(fs::StorageService),
+    S3(S3Client, s3::StorageConfig),
+    Gridfs(GridFsBucket),
+    Azure(azure::StorageService),
+}
+
+/// Object in the storage service.
+#[derive(Clone)]
+pub struct Object {
+    pub size: u64,
+    pub content_type: Option<String>,
+
+    /// when the object was last modified, in milliseconds since the Unix epoch.
+    pub last_modified: Option<u128>,
+    source: Source,
+}
+
+#[derive(Clone)]
+enum Source {
+    Path(PathBuf),
+    Key(String),
+    Id(Bson),
+    Bytes(Bytes),
+}
+
+impl Object {
+    /// Creates an object out of data that is already in memory, like a transformed image.
+    pub fn from_bytes(data: Bytes, last_modified: Option<u128>) -> Object {
+        Object {
+            size: data.len() as u64,
+            content_type: None,
+            last_modified,
+            source: Source::Bytes(data),
+        }
+    }
+}
+
+impl Streamer {
+    /// Creates the storage service that `config` describes, along with a streamer that
+    /// shares its connection to the storage backend.
+    pub fn new(config: &storage::Config) -> eyre::Result<(StorageService, Streamer)> {
+        Ok(match config.clone() {
+            storage::Config::Filesystem(config) => {
+                let fs = fs::StorageService::with_config(config);
+                (StorageService::Filesystem(fs.clone()), Streamer::Filesystem(fs))
+            }
+
+            storage::Config::S3(config) => {
+                let client = S3Client::from_conf(config.clone().into());
+                let storage = s3::StorageService::with_sdk_conf(client.config().clone()).with_config(config.clone());
+
+                (StorageService::S3(storage), Streamer::S3(client, config))
+            }
+
+            storage::Config::Gridfs(config) => {
+                let client = Client::with_options(config.client_options.clone())?;
+                let bucket = client
+                    .database(config.database.as_deref().unwrap_or("mydb"))
+                    .gridfs_bucket(Some(config.as_ref().clone().into()));
+
+                (
+                    StorageService::Gridfs(gridfs::StorageService::from_client(&client, *config)),
+                    Streamer::Gridfs(bucket),
+                )
+            }
+
+            storage::Config::Azure(config) => {
+                let storage = azure::StorageService::new(config)?;
+                (StorageService::Azure(storage.clone()), Streamer::Azure(storage))
+            }
+        })
+    }
+
+    /// Returns the object at `path` without reading its contents, if it exists.
+    pub async fn stat(&self, path: &str) -> eyre::Result<Option<Object>> {
+        match self {
+            Streamer::Filesystem(fs) => {
+                let Some(path) = fs.normalize(path)? else {
+                    return Ok(None);
+                };
+
+                let metadata = match tokio::fs::metadata(&path).await {
+                    Ok(metadata) if metadata.is_file() => metadata,
+                    Ok(_) => return Ok(None),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                    Err(e) => return Err(e.into()),
+                };
+
+                Ok(Some(Object {
+                    size: metadata.len(),
+                    content_type: None,
+                    last_modified: metadata
+                        .modified()
+                        .ok()
+                        .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+                        .map(|modified| modified.as_millis()),
+
+                    source: Source::Path(path),
+                }))
+            }
+
+            Streamer::S3(client, config) => {
+                let key = s3_key(config, path);
+                let object = match client.head_object().bucket(&config.bucket).key(&key).send().await {
+                    Ok(object) => object,
+                    Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => return Ok(None),
+                    Err(e) => return Err(e.into()),
+                };
+
+                Ok(Some(Object {
+                    size: object
+                        .content_length()
+                        .unwrap_or_default()
+                        .try_into()
+                        .unwrap_or_default(),
+                    content_type: object.content_type().map(ToOwned::to_owned),
+                    last_modified: object
+                        .last_modified()
+                        .and_then(|modified| modified.to_millis().ok())
+                        .and_then(|millis| u128::try_from(millis).ok()),
+
+                    source: Source::Key(key),
+                }))
+            }
+
+            Streamer::Gridfs(bucket) => {
+                let filename = path.trim_start_matches("~/").trim_start_matches("./");
+                let Some(file) = bucket.find_one(doc! { "filename": filename }).await? else {
+                    return Ok(None);
+                };
+
+                Ok(Some(Object {
+                    size: file.length,
+                    content_type: file
+                        .metadata
+                        .as_ref()
+                        .and_then(|metadata| metadata.get_str("contentType").ok())
+                        .map(ToOwned::to_owned),
+
+                    last_modified: u128::try_from(file.upload_date.timestamp_millis()).ok(),
+                    source: Source::Id(file.id),
+                }))
+            }
+
+            Streamer::Azure(storage) => {
+                let name = path.trim_start_matches("~/").trim_start_matches("./");
+                let properties = match storage.blob_client(name).get_properties().await {
+                    Ok(properties) => properties.blob.properties,
+                    Err(e) if e.as_http_error().is_some_and(|e| e.status() == StatusCode::NotFound) => {
+                        return Ok(None);
+                    }
+
+                    Err(e) => return Err(e.into()),
+                };
+
+                Ok(Some(Object {
+                    size: properties.content_length,
+                    content_type: Some(properties.content_type),
+                    last_modified: u128::try_from(properties.last_modified.unix_timestamp_nanos() / 1_000_000).ok(),
+                    source: Source::Key(name.to_owned()),
+                }))
+            }
+        }
+    }
+
+    /// Opens a stream of `range` (or all) of the contents of `object`.
+    pub async fn read(&self, object: &Object, range: Option<RangeInclusive<u64>>) -> eyre::Result<ByteStream> {
+        let (start, length) = match range {
+            Some(ref range) => (*range.start(), range.end() - range.start() + 1),
+            None => (0, object.size),
+        };
+
+        match (self, &object.source) {
+            (_, Source::Bytes(data)) => {
+                let data = data.slice(start as usize..(start + length) as usize);
+                Ok(stream::once(async move { Ok(data) }).boxed())
+            }
+
+            (Streamer::Filesystem(_), Source::Path(path)) => {
+                let mut file = tokio::fs::File::open(path).await?;
+                file.seek(SeekFrom::Start(start)).await?;
+
+                Ok(ReaderStream::new(file.take(length)).boxed())
+            }
+
+            (Streamer::S3(client, config), Source::Key(key)) => {
+                let mut request = client.get_object().bucket(&config.bucket).key(key);
+                if let Some(range) = range {
+                    request = request.range(format!("bytes={}-{}", range.start(), range.end()));
+                }
+
+                let object = request.send().await?;
+                Ok(ReaderStream::new(object.body.into_async_read()).boxed())
+            }
+
+            (Streamer::Azure(_), Source::Key(_)) if length == 0 => Ok(stream::empty().boxed()),
+            (Streamer::Azure(storage), Source::Key(name)) => {
+                let pages = storage
+                    .blob_client(name)
+                    .get()
+                    .range(start..start + length)
+                    .into_stream()
+                    .map_err(io::Error::other)
+                    .map_ok(|page| page.data.map_err(io::Error::other))
+                    .try_flatten();
+
+                Ok(pages.boxed())
+            }
+
+            (Streamer::Gridfs(bucket), Source::Id(id)) => {
+                // GridFS can't seek, so everything before the range is skipped over
+                let mut download = bucket.open_download_stream(id.clone()).await?;
+                futures_util::io::copy(&mut (&mut download).take(start), &mut futures_util::io::sink()).await?;
+
+                Ok(ReaderStream::new(download.take(length).compat()).boxed())
+            }
+
+            _ => unreachable!("objects can only be read by the streamer that found them"),
+        }
+    }
+
+    /// Reads all of the contents of `object` into memory.
+    pub async fn bytes(&self, object: &Object) -> eyre::Result<Bytes> {
+        if let Source::Bytes(ref data) = object.source {
+            return Ok(data.clone());
+        }
+
+        let chunks: Vec<Bytes> = self.read(object, None).await?.try_collect().await?;
+        Ok(chunks.concat().into())
+    }
+
+    /// Stores the contents of the file at `file` as `path`, without reading it into memory
+    /// if the storage service can be streamed to.
+    pub async fn upload(&self, path: &str, content_type: &str, file: &Path) -> eyre::Result<()> {
+        match self {
+            Streamer::Filesystem(fs) => {
+                let Some(path) = fs.normalize(path)? else {
+                    bail!("unable to normalize path `{path}`");
+                };
+
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+
+                // copied next to the image first and then renamed over it, so that it's never
+                // served half-written
+                let temp = tempfile::Builder::new()
+                    .prefix(".ume-")
+                    .tempfile_in(path.parent().unwrap_or(Path::new(".")))?
+                    .into_temp_path();
+
+                tokio::fs::copy(file, &temp).await?;
+                tokio::fs::rename(&temp, &path).await?;
+                temp.keep()?;
+            }
+
+            Streamer::S3(client, config) => {
+                let length = tokio::fs::metadata(file).await?.len();
+                client
+                    .put_object()
+                    .bucket(&config.bucket)
+                    .key(s3_key(config, path))
+                    .acl(
+                        config
+                            .default_object_acl
+                            .clone()
+                            .unwrap_or(ObjectCannedAcl::BucketOwnerFullControl),
+                    )
+                    .body(S3ByteStream::from_path(file).await?)
+                    .content_type(content_type)
+                    .content_length(length.try_into()?)
+                    .send()
+                    .await?;
+            }
+
+            Streamer::Gridfs(bucket) => {
+                let filename = path.trim_start_matches("~/").trim_start_matches("./");
+                let mut upload = bucket
+                    .open_upload_stream(filename)
+                    .with_options(
+                        GridFsUploadOptions::builder()
+                            .metadata(doc! { "contentType": content_type })
+                            .build(),
+                    )
+                    .await?;
+
+                let file = tokio::fs::File::open(file).await?;
+                futures_util::io::copy(file.compat(), &mut upload).await?;
+                upload.close().await?;
+            }
+
+            Streamer::Azure(storage) => {
+                storage
+                    .upload(
+                        path,
+                        UploadRequest::default()
+                            .with_content_type(Some(content_type))
+                            .with_data(tokio::fs::read(file).await?),
+                    )
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Resolves the key of `path` the same way `remi-s3` does, which doesn't expose it.
+fn s3_key(config: &s3::StorageConfig, path: &str) -> String {
+    let path = path.trim_start_matches("~/").trim_start_matches("./");
+    match config.prefix.as_deref().unwrap_or_default() {
+        "" => path.to_owned(),
+        prefix => format!("{}/{path}", prefix.trim_start_matches("~/").trim_start_matches("./")),
+    }
+}
+
+/// Builds a `multipart/byteranges` body out of `ranges` of `object`, returning how long the
+/// body is along with a stream of it.
+pub fn multipart(
+    streamer: Streamer,
+    object: Object,
+    ranges: Vec<RangeInclusive<u64>>,
+    content_type: &str,
+    boundary: &str,
+) -> (u64, ByteStream) {
+    let headers = ranges
+        .iter()
+        .map(|range| {
+            Bytes::from(format!(
+                "\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                range.start(),
+                range.end(),
+                object.size
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    let trailer = Bytes::from(format!("\r\n--{boundary}--\r\n"));
+    let length = headers.iter().map(|header| header.len() as u64).sum::<u64>()
+        + ranges.iter().map(|range| range.end() - range.start() + 1).sum::<u64>()
+        + trailer.len() as u64;
+
+    let parts = stream::iter(headers.into_iter().zip(ranges)).flat_map(move |(header, range)| {
+        let (streamer, object) = (streamer.clone(), object.clone());
+        let data = stream::once(async move { streamer.read(&object, Some(range)).await.map_err(io::Error::other) });
+
+        stream::once(async move { Ok(header) }).chain(data.try_flatten())
+    });
+
+    (length, parts.chain(stream::once(async move { Ok(trailer) })).boxed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Streamer, s3_key};
+    use crate::server::spool::Spool;
+    use azalia::remi::{fs, s3::StorageConfig};
+    use tempfile::TempDir;
+
+    #[test]
+    fn s3_keys() {
+        let config = StorageConfig::default();
+        assert_eq!(s3_key(&config, "./weow.png"), "weow.png");
+        assert_eq!(
+            s3_key(&config, "~/.ume/thumbnails/weow.png"),
+            ".ume/thumbnails/weow.png"
+        );
+
+        let config = StorageConfig {
+            prefix: Some(String::from("./images/ume")),
+            ..Default::default()
+        };
+
+        assert_eq!(s3_key(&config, "./weow.png"), "images/ume/weow.png");
+        assert_eq!(s3_key(&config, "weow.png"), "images/ume/weow.png");
+    }
+
+    #[tokio::test]
+    async fn files_are_never_read_half_written() {
+        let dir = TempDir::new().unwrap();
+        let streamer = Streamer::Filesystem(fs::StorageService::new(dir.path()));
+        let images = [vec![1; 4 * 1024 * 1024], vec![2; 4 * 1024 * 1024]];
+        let spools = [
+            Spool::from_bytes(&images[0]).await.unwrap(),
+            Spool::from_bytes(&images[1]).await.unwrap(),
+        ];
+
+        streamer
+            .upload("./weow.png", "image/png", spools[0].path())
+            .await
+            .unwrap();
+
+        let path = dir.path().join("weow.png");
+        let reader = tokio::spawn(async move {
+            for _ in 0..200 {
+                let data = tokio::fs::read(&path).await.unwrap();
+                assert!(
+                    images.contains(&data),
+                    "read {} bytes of a half-written file",
+                    data.len()
+                );
+                tokio::task::yield_now().await;
+            }
+        });
+
+        for i in 0..20 {
+            streamer
+                .upload("./weow.png", "image/png", spools[i % 2].path())
+                .await
+                .unwrap();
+        }
+
+        reader.await.unwrap();
+
+        let names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+
+        assert_eq!(names, ["weow.png"]);
+    }
+}
@@ -0,0 +1,575 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::format::SVG_NAMESPACE;
+use quick_xml::{
+    NsReader, Writer,
+    escape::resolve_xml_entity,
+    events::{BytesStart, BytesText, Event},
+    name::{Namespace, ResolveResult},
+};
+use resvg::{
+    tiny_skia::{Pixmap, Transform},
+    usvg::{self, ImageHrefResolver, fontdb},
+};
+use std::sync::{Arc, OnceLock};
+
+const XLINK_NAMESPACE: &[u8] = b"http://www.w3.org/1999/xlink";
+const XML_NAMESPACE: &[u8] = b"http://www.w3.org/XML/1998/namespace";
+
+/// Elements in the SVG namespace that are kept. Everything else, including elements in
+/// any other namespace, is removed along with everything inside of it.
+const ELEMENTS: &[&str] = &[
+    "a",
+    "animate",
+    "animateMotion",
+    "animateTransform",
+    "circle",
+    "clipPath",
+    "defs",
+    "desc",
+    "ellipse",
+    "feBlend",
+    "feColorMatrix",
+    "feComponentTransfer",
+    "feComposite",
+    "feConvolveMatrix",
+    "feDiffuseLighting",
+    "feDisplacementMap",
+    "feDistantLight",
+    "feDropShadow",
+    "feFlood",
+    "feFuncA",
+    "feFuncB",
+    "feFuncG",
+    "feFuncR",
+    "feGaussianBlur",
+    "feImage",
+    "feMerge",
+    "feMergeNode",
+    "feMorphology",
+    "feOffset",
+    "fePointLight",
+    "feSpecularLighting",
+    "feSpotLight",
+    "feTile",
+    "feTurbulence",
+    "filter",
+    "g",
+    "image",
+    "line",
+    "linearGradient",
+    "marker",
+    "mask",
+    "metadata",
+    "mpath",
+    "path",
+    "pattern",
+    "polygon",
+    "polyline",
+    "radialGradient",
+    "rect",
+    "set",
+    "stop",
+    "style",
+    "svg",
+    "switch",
+    "symbol",
+    "text",
+    "textPath",
+    "title",
+    "tspan",
+    "use",
+    "view",
+];
+
+/// Animation elements, which are only kept if they animate one of [`ATTRIBUTES`].
+const ANIMATIONS: &[&str] = &["animate", "animateMotion", "animateTransform", "set"];
+
+/// Attributes without a namespace that are kept. `href` is kept separately if it points
+/// to something in the same document.
+const ATTRIBUTES: &[&str] = &[
+    // core
+    "id",
+    "class",
+    "style",
+    "lang",
+    "tabindex",
+    "systemLanguage",
+    // geometry
+    "transform",
+    "x",
+    "y",
+    "x1",
+    "x2",
+    "y1",
+    "y2",
+    "cx",
+    "cy",
+    "r",
+    "rx",
+    "ry",
+    "fx",
+    "fy",
+    "fr",
+    "width",
+    "height",
+    "d",
+    "points",
+    "pathLength",
+    "viewBox",
+    "preserveAspectRatio",
+    "version",
+    "baseProfile",
+    // presentation
+    "fill",
+    "fill-opacity",
+    "fill-rule",
+    "stroke",
+    "stroke-dasharray",
+    "stroke-dashoffset",
+    "stroke-linecap",
+    "stroke-linejoin",
+    "stroke-miterlimit",
+    "stroke-opacity",
+    "stroke-width",
+    "opacity",
+    "color",
+    "color-interpolation",
+    "color-interpolation-filters",
+    "color-rendering",
+    "display",
+    "visibility",
+    "overflow",
+    "clip",
+    "clip-path",
+    "clip-rule",
+    "mask",
+    "filter",
+    "flood-color",
+    "flood-opacity",
+    "lighting-color",
+    "stop-color",
+    "stop-opacity",
+    "marker-start",
+    "marker-mid",
+    "marker-end",
+    "shape-rendering",
+    "image-rendering",
+    "vector-effect",
+    "paint-order",
+    "mix-blend-mode",
+    "isolation",
+    // text
+    "font-family",
+    "font-size",
+    "font-size-adjust",
+    "font-stretch",
+    "font-style",
+    "font-variant",
+    "font-weight",
+    "text-anchor",
+    "text-decoration",
+    "text-rendering",
+    "dominant-baseline",
+    "alignment-baseline",
+    "baseline-shift",
+    "letter-spacing",
+    "word-spacing",
+    "writing-mode",
+    "direction",
+    "unicode-bidi",
+    "dx",
+    "dy",
+    "rotate",
+    "textLength",
+    "lengthAdjust",
+    "startOffset",
+    "method",
+    "spacing",
+    "side",
+    // gradients, patterns, clipping paths, masks and markers
+    "offset",
+    "gradientUnits",
+    "gradientTransform",
+    "spreadMethod",
+    "patternUnits",
+    "patternContentUnits",
+    "patternTransform",
+    "clipPathUnits",
+    "maskUnits",
+    "maskContentUnits",
+    "markerWidth",
+    "markerHeight",
+    "markerUnits",
+    "refX",
+    "refY",
+    "orient",
+    // filters
+    "filterUnits",
+    "primitiveUnits",
+    "in",
+    "in2",
+    "result",
+    "stdDeviation",
+    "mode",
+    "type",
+    "values",
+    "tableValues",
+    "slope",
+    "intercept",
+    "amplitude",
+    "exponent",
+    "k1",
+    "k2",
+    "k3",
+    "k4",
+    "operator",
+    "radius",
+    "scale",
+    "xChannelSelector",
+    "yChannelSelector",
+    "baseFrequency",
+    "numOctaves",
+    "seed",
+    "stitchTiles",
+    "order",
+    "kernelMatrix",
+    "divisor",
+    "bias",
+    "targetX",
+    "targetY",
+    "edgeMode",
+    "kernelUnitLength",
+    "preserveAlpha",
+    "surfaceScale",
+    "diffuseConstant",
+    "specularConstant",
+    "specularExponent",
+    "azimuth",
+    "elevation",
+    "z",
+    "pointsAtX",
+    "pointsAtY",
+    "pointsAtZ",
+    "limitingConeAngle",
+    // animations
+    "attributeName",
+    "attributeType",
+    "begin",
+    "dur",
+    "end",
+    "min",
+    "max",
+    "restart",
+    "repeatCount",
+    "repeatDur",
+    "calcMode",
+    "keyTimes",
+    "keySplines",
+    "keyPoints",
+    "from",
+    "to",
+    "by",
+    "additive",
+    "accumulate",
+    "path",
+    // `<style>`
+    "media",
+];
+
+/// Embedded images that `href` attributes can point to, next to fragments in the same document.
+const DATA_IMAGES: &[&str] = &["data:image/png", "data:image/jpeg", "data:image/gif", "data:image/webp"];
+
+/// Keeps only the SVG elements and attributes that can't run scripts or reference anything
+/// outside of the document. Comments, processing instructions and the document type
+/// (which could declare entities) are removed as well.
+pub fn sanitize(data: &[u8]) -> eyre::Result<Vec<u8>> {
+    let mut reader = NsReader::from_reader(data);
+    let mut writer = Writer::new(Vec::with_capacity(data.len()));
+
+    // how deep we are in an element that is being removed
+    let mut skipping = 0usize;
+
+    // contents of the `<style>` element we're in, which are only kept if they don't
+    // reference anything external
+    let mut style: Option<String> = None;
+
+    loop {
+        let (namespace, event) = reader.read_resolved_event()?;
+        let svg = namespace == ResolveResult::Bound(Namespace(SVG_NAMESPACE));
+
+        match event {
+            Event::Start(_) if skipping > 0 => skipping += 1,
+            Event::End(_) if skipping > 0 => skipping -= 1,
+            _ if skipping > 0 => {}
+
+            // `<style>` elements can only contain text
+            Event::Start(_) if style.is_some() => skipping = 1,
+            Event::Empty(_) if style.is_some() => {}
+
+            Event::Start(ref element) if !svg || !is_allowed(element) => skipping = 1,
+            Event::Empty(ref element) if !svg || !is_allowed(element) => {}
+
+            Event::Start(element) => {
+                if element.local_name().as_ref() == b"style" {
+                    style = Some(String::new());
+                }
+
+                writer.write_event(Event::Start(clean(&reader, &element)?))?;
+            }
+
+            Event::Empty(element) => writer.write_event(Event::Empty(clean(&reader, &element)?))?,
+            Event::End(element) => {
+                if let Some(contents) = style.take().filter(|contents| !references_external(contents)) {
+                    writer.write_event(Event::Text(BytesText::new(&contents)))?;
+                }
+
+                writer.write_event(Event::End(element))?;
+            }
+
+            Event::Text(text) if style.is_some() => style.as_mut().unwrap().push_str(&text.decode()?),
+            Event::CData(text) if style.is_some() => style.as_mut().unwrap().push_str(&text.decode()?),
+            Event::GeneralRef(reference) if style.is_some() => {
+                // entities that the document type declared are gone along with it
+                let contents = style.as_mut().unwrap();
+                match reference.resolve_char_ref()? {
+                    Some(ch) => contents.push(ch),
+                    None => contents.push_str(resolve_xml_entity(&reference.decode()?).unwrap_or_default()),
+                }
+            }
+
+            event @ (Event::Text(_) | Event::CData(_) | Event::GeneralRef(_) | Event::Decl(_)) => {
+                writer.write_event(event)?
+            }
+
+            Event::Comment(_) | Event::PI(_) | Event::DocType(_) => {}
+            Event::Eof => break,
+        }
+    }
+
+    Ok(writer.into_inner())
+}
+
+/// Renders a SVG image as a PNG image that fits within `max_dimension` pixels.
+pub fn rasterize(data: &[u8], max_dimension: u32) -> eyre::Result<Vec<u8>> {
+    let options = usvg::Options {
+        fontdb: fonts(),
+
+        // images can only be embedded, never loaded from the filesystem
+        image_href_resolver: ImageHrefResolver {
+            resolve_data: ImageHrefResolver::default_data_resolver(),
+            resolve_string: Box::new(|_, _| None),
+        },
+
+        ..Default::default()
+    };
+
+    let tree = usvg::Tree::from_data(data, &options)?;
+    let size = tree.size();
+    let scale = (max_dimension as f32 / size.width().max(size.height())).min(1.0);
+
+    let size = size
+        .to_int_size()
+        .scale_by(scale)
+        .ok_or_else(|| eyre!("svg image is too small"))?;
+    let mut pixmap = Pixmap::new(size.width(), size.height()).ok_or_else(|| eyre!("svg image is too small"))?;
+    resvg::render(&tree, Transform::from_scale(scale, scale), &mut pixmap.as_mut());
+
+    Ok(pixmap.encode_png()?)
+}
+
+/// Returns the system fonts, which are only loaded the first time a SVG is rasterized.
+fn fonts() -> Arc<fontdb::Database> {
+    static FONTS: OnceLock<Arc<fontdb::Database>> = OnceLock::new();
+    FONTS
+        .get_or_init(|| {
+            let mut fonts = fontdb::Database::new();
+            fonts.load_system_fonts();
+
+            Arc::new(fonts)
+        })
+        .clone()
+}
+
+/// Returns whether `element` (which is in the SVG namespace) is kept. Animations are only
+/// kept if they animate an attribute that is kept, so they can't set a `javascript:` link
+/// or an event handler.
+fn is_allowed(element: &BytesStart) -> bool {
+    let name = element.local_name();
+    let Ok(name) = std::str::from_utf8(name.as_ref()) else {
+        return false;
+    };
+
+    if !ELEMENTS.contains(&name) {
+        return false;
+    }
+
+    !ANIMATIONS.contains(&name)
+        || element
+            .attributes()
+            .flatten()
+            .filter(|attr| attr.key.as_ref() == b"attributeName")
+            .all(|attr| {
+                attr.unescape_value()
+                    .is_ok_and(|value| ATTRIBUTES.contains(&value.trim()))
+            })
+}
+
+/// Returns a copy of `element` with only the attributes that are kept. Namespaces are only
+/// declared if they're the SVG or XLink namespace, since nothing else is kept.
+fn clean<'a>(reader: &NsReader<&[u8]>, element: &BytesStart<'a>) -> eyre::Result<BytesStart<'a>> {
+    let mut cleaned = element.clone();
+    cleaned.clear_attributes();
+
+    for attr in element.attributes() {
+        let attr = attr?;
+        let value = attr.unescape_value()?;
+
+        let keep = match attr.key.as_namespace_binding() {
+            Some(_) => [SVG_NAMESPACE, XLINK_NAMESPACE].contains(&value.as_bytes()),
+            None => match reader.resolve_attribute(attr.key) {
+                (ResolveResult::Unbound, name) if name.as_ref() == b"href" => is_local(&value),
+                (ResolveResult::Bound(Namespace(XLINK_NAMESPACE)), name) if name.as_ref() == b"href" => {
+                    is_local(&value)
+                }
+
+                (ResolveResult::Unbound, name) => {
+                    std::str::from_utf8(name.as_ref()).is_ok_and(|name| ATTRIBUTES.contains(&name))
+                        && !references_external(&value)
+                }
+
+                (ResolveResult::Bound(Namespace(XML_NAMESPACE)), name) => {
+                    matches!(name.as_ref(), b"space" | b"lang")
+                }
+
+                _ => false,
+            },
+        };
+
+        if keep {
+            cleaned.push_attribute(attr);
+        }
+    }
+
+    Ok(cleaned)
+}
+
+/// Returns whether a link points to a fragment in the same document or an embedded image.
+fn is_local(target: &str) -> bool {
+    let target = target.trim();
+    target.starts_with('#') || DATA_IMAGES.iter().any(|prefix| starts_with_ignore_case(target, prefix))
+}
+
+/// Returns whether `value` references anything outside of the document, like a `url()`
+/// that doesn't point to a fragment or an embedded image, or a CSS `@import`. CSS escapes
+/// count as well since they could hide either of them.
+fn references_external(value: &str) -> bool {
+    let value = value.to_ascii_lowercase();
+    if value.contains('\\') || value.contains("@import") {
+        return true;
+    }
+
+    value.match_indices("url(").any(|(at, _)| {
+        let target = value[at + 4..].trim_start().trim_start_matches(['"', '\'']);
+        !(target.starts_with('#') || DATA_IMAGES.iter().any(|prefix| target.starts_with(prefix)))
+    })
+}
+
+fn starts_with_ignore_case(value: &str, prefix: &str) -> bool {
+    value
+        .get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sanitize;
+
+    #[test]
+    fn removes_scripts_and_external_references() {
+        let svg = br##"<?xml version="1.0"?>
+<!DOCTYPE svg [<!ENTITY x "y">]>
+<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" onload="alert(1)">
+  <script>alert(1)</script>
+  <foreignObject><div xmlns="http://www.w3.org/1999/xhtml">hi</div></foreignObject>
+  <style>@import url(https://example.com/a.css);</style>
+  <style>rect { fill: url(#g) }</style>
+  <a xlink:href="javascript:alert(1)"><text>click</text></a>
+  <use href="#g"/>
+  <image href="https://example.com/a.png"/>
+  <rect fill="url(https://example.com/#g)" stroke="red" style="fill:url(&#x27;https://example.com&#x27;)"/>
+  <set attributeName="href" to="javascript:alert(1)"/>
+</svg>"##;
+
+        let sanitized = String::from_utf8(sanitize(svg).unwrap()).unwrap();
+        for removed in [
+            "script",
+            "alert",
+            "onload",
+            "foreignObject",
+            "example.com",
+            "@import",
+            "DOCTYPE",
+            "<set",
+        ] {
+            assert!(!sanitized.contains(removed), "`{removed}` wasn't removed:\n{sanitized}");
+        }
+
+        for kept in [
+            "<?xml",
+            "<style>rect { fill: url(#g) }</style>",
+            "<use href=\"#g\"/>",
+            "<text>click</text>",
+            "stroke=\"red\"",
+        ] {
+            assert!(sanitized.contains(kept), "`{kept}` was removed:\n{sanitized}");
+        }
+    }
+
+    #[test]
+    fn keeps_only_svg_elements_and_attributes() {
+        let svg = br##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:html="http://www.w3.org/1999/xhtml" xmlns:ev="http://www.w3.org/2001/xml-events" data-x="1">
+  <html:iframe src="https://example.com"><html:b>hi</html:b></html:iframe>
+  <blink>hi</blink>
+  <g xmlns="http://www.w3.org/1999/xhtml"><rect/></g>
+  <s:rect xmlns:s="http://www.w3.org/2000/svg" width="4" ev:event="click" html:onclick="alert(1)" xml:space="preserve"/>
+  <animate attributeName="fill" to="red"/>
+</svg>"##;
+
+        let sanitized = String::from_utf8(sanitize(svg).unwrap()).unwrap();
+        for removed in [
+            "xhtml",
+            "xml-events",
+            "data-x",
+            "iframe",
+            "<html:b",
+            "blink",
+            "<g",
+            "ev:event",
+            "onclick",
+        ] {
+            assert!(!sanitized.contains(removed), "`{removed}` wasn't removed:\n{sanitized}");
+        }
+
+        for kept in [
+            "<svg xmlns=\"http://www.w3.org/2000/svg\">",
+            "<s:rect xmlns:s=\"http://www.w3.org/2000/svg\" width=\"4\" xml:space=\"preserve\"/>",
+            "<animate attributeName=\"fill\" to=\"red\"/>",
+        ] {
+            assert!(sanitized.contains(kept), "`{kept}` was removed:\n{sanitized}");
+        }
+    }
+}
@@ -0,0 +1,84 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{metadata::Image, transform};
+use crate::config::transforms::{Fit, Transform};
+use azalia::remi::{
+    StorageService,
+    core::{StorageService as _, UploadRequest},
+};
+use image::ImageReader;
+use std::path::Path;
+
+/// Prefix in the storage service where thumbnails live. Thumbnails are keyed by the
+/// SHA-256 digest of the image, so deduplicated images share them as well.
+pub const PREFIX: &str = "./.ume/thumbnails";
+
+/// Returns the path of the thumbnail of the image with the SHA-256 digest `sha256`.
+pub fn path(sha256: &str, size: u32) -> String {
+    format!("{PREFIX}/{size}/{sha256}")
+}
+
+/// Generates a thumbnail for every size in `sizes` that the image is larger than, from the
+/// image's contents at `source`. Images that already fit within a size don't get a thumbnail
+/// for it, the original is served instead.
+pub async fn generate(
+    storage: &StorageService,
+    pool: &transform::Pool,
+    image: &Image,
+    source: &Path,
+    sizes: &[u32],
+) -> eyre::Result<()> {
+    let (Some(width), Some(height)) = (image.width, image.height) else {
+        return Ok(());
+    };
+
+    for &size in sizes.iter().filter(|&&size| width > size || height > size) {
+        let transform = Transform {
+            w: Some(size),
+            h: Some(size),
+            fit: Some(Fit::Contain),
+            ..Default::default()
+        };
+
+        // the image is only read (and decoded) once the pool has room for it
+        let source = source.to_owned();
+        let (thumbnail, content_type) = pool
+            .run(move || transform::apply_to(ImageReader::open(source)?.with_guessed_format()?, &transform))
+            .await?;
+        storage
+            .upload(
+                path(&image.sha256, size),
+                UploadRequest::default()
+                    .with_content_type(Some(content_type))
+                    .with_data(thumbnail),
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Removes every thumbnail of the image with the SHA-256 digest `sha256`.
+pub async fn forget(storage: &StorageService, sha256: &str, sizes: &[u32]) -> eyre::Result<()> {
+    for &size in sizes {
+        let path = path(sha256, size);
+        if storage.exists(&path).await? {
+            storage.delete(path).await?;
+        }
+    }
+
+    Ok(())
+}
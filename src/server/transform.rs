@@ -0,0 +1,272 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config::transforms::{self, Fit, Format, Transform};
+use axum::body::Bytes;
+use image::{
+    DynamicImage, ImageFormat, ImageReader, ImageResult,
+    codecs::{jpeg::JpegEncoder, png::PngEncoder, webp::WebPEncoder},
+    imageops::FilterType,
+};
+use serde::Deserialize;
+use std::{
+    io::{BufRead, Cursor, Seek},
+    sync::Arc,
+};
+use tokio::sync::Semaphore;
+
+const DEFAULT_QUALITY: u8 = 80;
+
+/// Query parameters that `GET /images/{name}` accepts.
+#[derive(Debug, Default, Deserialize)]
+pub struct Query {
+    w: Option<u32>,
+    h: Option<u32>,
+    fit: Option<Fit>,
+    format: Option<Format>,
+    q: Option<u8>,
+
+    /// name of a preset from the `[transforms.presets]` table.
+    pub preset: Option<String>,
+
+    /// signature that was minted by `POST /images/{name}/sign`.
+    pub sig: Option<String>,
+
+    /// when the signature stops being valid, in seconds since the Unix epoch.
+    pub expires: Option<i64>,
+
+    /// password of a password-protected image.
+    pub password: Option<String>,
+}
+
+impl Query {
+    /// Returns the transformation that was given with the `w`, `h`, `fit`, `format`, and `q`
+    /// query parameters.
+    pub fn transform(&self) -> Transform {
+        Transform {
+            w: self.w,
+            h: self.h,
+            fit: self.fit,
+            format: self.format,
+            q: self.q,
+        }
+    }
+}
+
+/// Returns `true` if `transform` doesn't change the image.
+pub fn is_empty(transform: &Transform) -> bool {
+    *transform == Transform::default()
+}
+
+/// Returns the query parameters of `transform` in the order that they're signed in.
+pub fn params(transform: &Transform) -> Vec<(&'static str, String)> {
+    let mut params = Vec::new();
+    if let Some(w) = transform.w {
+        params.push(("w", w.to_string()));
+    }
+
+    if let Some(h) = transform.h {
+        params.push(("h", h.to_string()));
+    }
+
+    if let Some(fit) = transform.fit {
+        params.push((
+            "fit",
+            String::from(match fit {
+                Fit::Contain => "contain",
+                Fit::Cover => "cover",
+                Fit::Fill => "fill",
+            }),
+        ));
+    }
+
+    if let Some(format) = transform.format {
+        params.push((
+            "format",
+            String::from(match format {
+                Format::Png => "png",
+                Format::Jpeg => "jpeg",
+                Format::Webp => "webp",
+            }),
+        ));
+    }
+
+    if let Some(q) = transform.q {
+        params.push(("q", q.to_string()));
+    }
+
+    params
+}
+
+/// Checks that `transform` is within the limits of `config`.
+pub fn validate(config: &transforms::Config, transform: &Transform) -> Result<(), String> {
+    for dimension in [transform.w, transform.h].into_iter().flatten() {
+        if dimension == 0 || dimension > config.max_dimension {
+            return Err(format!(
+                "width and height must be between 1 and {}",
+                config.max_dimension
+            ));
+        }
+    }
+
+    if transform.q.is_some_and(|q| !(1..=100).contains(&q)) {
+        return Err(String::from("quality must be between 1 and 100"));
+    }
+
+    Ok(())
+}
+
+/// Pool that limits how many images are transformed at the same time, since decoding
+/// and encoding images is expensive.
+#[derive(Clone)]
+pub struct Pool(Arc<Semaphore>);
+
+impl Pool {
+    pub fn new(permits: usize) -> Pool {
+        Pool(Arc::new(Semaphore::new(permits.max(1))))
+    }
+
+    /// Applies `transform` to `data` on a blocking thread once the pool has room for it.
+    pub async fn apply(&self, data: Bytes, transform: Transform) -> ImageResult<(Vec<u8>, &'static str)> {
+        self.run(move || apply(&data, &transform)).await
+    }
+
+    /// Runs `f` on a blocking thread once the pool has room for it.
+    pub async fn run<T: Send + 'static>(&self, f: impl FnOnce() -> T + Send + 'static) -> T {
+        let _permit = self.0.acquire().await.expect("semaphore was closed");
+        tokio::task::spawn_blocking(f)
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+}
+
+/// Applies `transform` to `data`, returning the encoded image and its content type.
+pub fn apply(data: &[u8], transform: &Transform) -> ImageResult<(Vec<u8>, &'static str)> {
+    apply_to(ImageReader::new(Cursor::new(data)).with_guessed_format()?, transform)
+}
+
+/// Applies `transform` to the image that `reader` reads, returning the encoded image and
+/// its content type.
+pub fn apply_to<R: BufRead + Seek>(
+    reader: ImageReader<R>,
+    transform: &Transform,
+) -> ImageResult<(Vec<u8>, &'static str)> {
+    let format = transform.format.unwrap_or(match reader.format() {
+        Some(ImageFormat::Jpeg) => Format::Jpeg,
+        Some(ImageFormat::WebP) => Format::Webp,
+        _ => Format::Png,
+    });
+
+    let image = resize(reader.decode()?, transform);
+    encode(&image, format, transform.q.unwrap_or(DEFAULT_QUALITY))
+}
+
+/// Encodes `image` as `format`, returning the encoded image and its content type. `quality`
+/// is only used for JPEG images.
+pub fn encode(image: &DynamicImage, format: Format, quality: u8) -> ImageResult<(Vec<u8>, &'static str)> {
+    let mut out = Cursor::new(Vec::new());
+    let content_type = match format {
+        Format::Png => {
+            image.write_with_encoder(PngEncoder::new(&mut out))?;
+            "image/png"
+        }
+
+        Format::Jpeg => {
+            let encoder = JpegEncoder::new_with_quality(&mut out, quality);
+
+            // JPEG doesn't support transparency
+            DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(encoder)?;
+            "image/jpeg"
+        }
+
+        // the `image` crate can only encode lossless WebP images, so the quality is ignored
+        Format::Webp => {
+            DynamicImage::ImageRgba8(image.to_rgba8()).write_with_encoder(WebPEncoder::new_lossless(&mut out))?;
+            "image/webp"
+        }
+    };
+
+    Ok((out.into_inner(), content_type))
+}
+
+fn resize(image: DynamicImage, transform: &Transform) -> DynamicImage {
+    let (width, height) = match (transform.w, transform.h) {
+        (None, None) => return image,
+        (Some(w), Some(h)) => (w, h),
+        (Some(w), None) => (w, scale(image.height(), w, image.width())),
+        (None, Some(h)) => (scale(image.width(), h, image.height()), h),
+    };
+
+    match transform.fit.unwrap_or_default() {
+        Fit::Contain => image.resize(width, height, FilterType::CatmullRom),
+        Fit::Cover => image.resize_to_fill(width, height, FilterType::CatmullRom),
+        Fit::Fill => image.resize_exact(width, height, FilterType::CatmullRom),
+    }
+}
+
+/// Scales `length` by `to / from`, so the aspect ratio is kept when only one dimension is given.
+fn scale(length: u32, to: u32, from: u32) -> u32 {
+    (u64::from(length) * u64::from(to) / u64::from(from.max(1))).clamp(1, u64::from(u32::MAX)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Transform, apply};
+    use crate::config::transforms::{Fit, Format};
+    use image::{GenericImageView, RgbaImage};
+    use std::io::Cursor;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut out = Cursor::new(Vec::new());
+        RgbaImage::new(width, height)
+            .write_to(&mut out, image::ImageFormat::Png)
+            .unwrap();
+
+        out.into_inner()
+    }
+
+    #[test]
+    fn resize_keeps_aspect_ratio() {
+        let (data, ct) = apply(
+            &png(640, 480),
+            &Transform {
+                w: Some(320),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(ct, "image/png");
+        assert_eq!(image::load_from_memory(&data).unwrap().dimensions(), (320, 240));
+    }
+
+    #[test]
+    fn cover_and_convert() {
+        let (data, ct) = apply(
+            &png(640, 480),
+            &Transform {
+                w: Some(100),
+                h: Some(100),
+                fit: Some(Fit::Cover),
+                format: Some(Format::Jpeg),
+                q: Some(50),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(ct, "image/jpeg");
+        assert_eq!(image::load_from_memory(&data).unwrap().dimensions(), (100, 100));
+    }
+}
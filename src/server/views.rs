@@ -0,0 +1,78 @@
+// 🐻‍❄️💐 ume: Easy, self-hostable, and flexible image host made in Rust
+// Copyright 2021-2025 Noel Towa <cutie@floofy.dev>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use axum::http::{HeaderMap, header};
+
+/// `User-Agent`s of the bots that chat apps and social networks use to show a preview of
+/// links. They get `uploads.preview_views` views of an image with a view limit on top of
+/// the ones that it was uploaded with.
+const PREVIEW_BOTS: &[&str] = &[
+    "discordbot",
+    "slackbot",
+    "slack-imgproxy",
+    "twitterbot",
+    "facebookexternalhit",
+    "telegrambot",
+    "whatsapp",
+    "linkedinbot",
+    "skypeuripreview",
+    "mattermost-bot",
+    "redditbot",
+    "embedly",
+    "iframely",
+    "mastodon",
+];
+
+/// Whether the request came from a bot that fetched the image to show a preview of it.
+pub fn is_preview_bot(headers: &HeaderMap) -> bool {
+    let Some(user_agent) = headers.get(header::USER_AGENT).and_then(|value| value.to_str().ok()) else {
+        return false;
+    };
+
+    let user_agent = user_agent.to_ascii_lowercase();
+    PREVIEW_BOTS.iter().any(|bot| user_agent.contains(bot))
+}
+
+/// Parses the view limit that the uploader asked for.
+pub fn parse_max_views(requested: &str) -> Result<u64, String> {
+    match requested.trim().parse::<u64>() {
+        Ok(0) => Err(String::from("view limit must be greater than zero")),
+        Ok(max_views) => Ok(max_views),
+        Err(_) => Err(format!("`{requested}` isn't a valid view limit")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue, header};
+
+    #[test]
+    fn is_preview_bot() {
+        let mut headers = HeaderMap::new();
+        assert!(!super::is_preview_bot(&headers));
+
+        headers.insert(
+            header::USER_AGENT,
+            HeaderValue::from_static("Mozilla/5.0 (compatible; Discordbot/2.0; +https://discordapp.com)"),
+        );
+        assert!(super::is_preview_bot(&headers));
+
+        headers.insert(
+            header::USER_AGENT,
+            HeaderValue::from_static("Mozilla/5.0 (X11; Linux x86_64; rv:133.0) Gecko/20100101 Firefox/133.0"),
+        );
+        assert!(!super::is_preview_bot(&headers));
+    }
+}